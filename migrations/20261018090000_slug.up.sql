@@ -0,0 +1,3 @@
+-- Add up migration script here
+ALTER TABLE urls ADD COLUMN slug TEXT;
+CREATE UNIQUE INDEX IF NOT EXISTS urls_slug_idx ON urls(slug);
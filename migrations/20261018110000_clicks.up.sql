@@ -0,0 +1,11 @@
+-- Add up migration script here
+CREATE TABLE IF NOT EXISTS clicks(
+    id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+    url_id INTEGER NOT NULL REFERENCES urls(id) ON DELETE CASCADE,
+    referrer TEXT,
+    user_agent TEXT,
+    accept_language TEXT,
+    client_hash TEXT NOT NULL,
+    created_at DATETIME NOT NULL
+);
+CREATE INDEX IF NOT EXISTS clicks_url_id_created_at_idx ON clicks(url_id, created_at);
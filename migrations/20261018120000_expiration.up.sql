@@ -0,0 +1,3 @@
+-- Add up migration script here
+ALTER TABLE urls ADD COLUMN expires_at DATETIME;
+ALTER TABLE urls ADD COLUMN fallback_url TEXT;
@@ -0,0 +1,59 @@
+-- Add up migration script here
+-- The urls are rebuilt without the unique `src`, as a destination can be
+-- shortened once more for every campaign that is added on redirect. The
+-- clicks point to the urls, so they are rebuilt too or dropping the urls
+-- would delete them.
+CREATE TABLE urls_copy AS SELECT * FROM urls;
+CREATE TABLE clicks_copy AS SELECT * FROM clicks;
+CREATE TABLE sequence_copy AS SELECT name, seq FROM sqlite_sequence;
+DROP TABLE clicks;
+DROP TABLE urls;
+CREATE TABLE urls(
+    id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+    src TEXT NOT NULL,
+    num INTEGER NOT NULL,
+    active BOOLEAN NOT NULL,
+    created_at DATETIME NOT NULL,
+    updated_at DATETIME NOT NULL,
+    slug TEXT,
+    expires_at DATETIME,
+    fallback_url TEXT,
+    password TEXT,
+    redirect_type TEXT,
+    forward_query BOOLEAN NOT NULL DEFAULT FALSE,
+    forward_path BOOLEAN NOT NULL DEFAULT FALSE,
+    utm_source TEXT,
+    utm_medium TEXT,
+    utm_campaign TEXT,
+    utm_term TEXT,
+    utm_content TEXT,
+    utm_on_redirect BOOLEAN NOT NULL DEFAULT FALSE
+);
+CREATE UNIQUE INDEX urls_slug_idx ON urls(slug);
+CREATE UNIQUE INDEX urls_src_idx ON urls(src) WHERE utm_on_redirect = FALSE;
+CREATE INDEX urls_utm_campaign_idx ON urls(utm_campaign);
+INSERT INTO urls (id, src, num, active, created_at, updated_at, slug, expires_at, fallback_url,
+    password, redirect_type, forward_query, forward_path)
+    SELECT id, src, num, active, created_at, updated_at, slug, expires_at, fallback_url,
+    password, redirect_type, forward_query, forward_path
+    FROM urls_copy;
+CREATE TABLE clicks(
+    id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+    url_id INTEGER NOT NULL REFERENCES urls(id) ON DELETE CASCADE,
+    referrer TEXT,
+    user_agent TEXT,
+    accept_language TEXT,
+    client_hash TEXT NOT NULL,
+    created_at DATETIME NOT NULL
+);
+CREATE INDEX clicks_url_id_created_at_idx ON clicks(url_id, created_at);
+INSERT INTO clicks SELECT id, url_id, referrer, user_agent, accept_language, client_hash,
+    created_at FROM clicks_copy;
+-- Keep counting the ids from where they were, so the ones of deleted urls
+-- are never given again
+DELETE FROM sqlite_sequence WHERE name IN ('urls', 'clicks');
+INSERT INTO sqlite_sequence SELECT name, seq FROM sequence_copy
+    WHERE name IN ('urls', 'clicks');
+DROP TABLE urls_copy;
+DROP TABLE clicks_copy;
+DROP TABLE sequence_copy;
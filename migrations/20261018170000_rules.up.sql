@@ -0,0 +1,13 @@
+-- Add up migration script here
+CREATE TABLE IF NOT EXISTS rules(
+    id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+    url_id INTEGER NOT NULL REFERENCES urls(id) ON DELETE CASCADE,
+    position INTEGER NOT NULL,
+    os TEXT,
+    device TEXT,
+    bot BOOLEAN,
+    src TEXT NOT NULL,
+    created_at DATETIME NOT NULL
+);
+CREATE INDEX IF NOT EXISTS rules_url_id_idx ON rules(url_id, position);
+ALTER TABLE clicks ADD COLUMN rule_id INTEGER;
@@ -0,0 +1,3 @@
+ALTER TABLE clicks DROP COLUMN variant_id;
+ALTER TABLE urls DROP COLUMN sticky_variant;
+DROP TABLE IF EXISTS variants;
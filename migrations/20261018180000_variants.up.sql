@@ -0,0 +1,11 @@
+-- Add up migration script here
+CREATE TABLE IF NOT EXISTS variants(
+    id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+    url_id INTEGER NOT NULL REFERENCES urls(id) ON DELETE CASCADE,
+    src TEXT NOT NULL,
+    weight INTEGER NOT NULL DEFAULT 1,
+    created_at DATETIME NOT NULL
+);
+CREATE INDEX IF NOT EXISTS variants_url_id_idx ON variants(url_id);
+ALTER TABLE urls ADD COLUMN sticky_variant BOOLEAN NOT NULL DEFAULT FALSE;
+ALTER TABLE clicks ADD COLUMN variant_id INTEGER;
@@ -0,0 +1,4 @@
+DROP TABLE IF EXISTS url_tags;
+DROP TABLE IF EXISTS tags;
+ALTER TABLE urls DROP COLUMN notes;
+ALTER TABLE urls DROP COLUMN title;
@@ -0,0 +1,100 @@
+CREATE TABLE urls_copy AS SELECT * FROM urls;
+CREATE TABLE clicks_copy AS SELECT * FROM clicks;
+CREATE TABLE rules_copy AS SELECT * FROM rules;
+CREATE TABLE variants_copy AS SELECT * FROM variants;
+CREATE TABLE url_tags_copy AS SELECT * FROM url_tags;
+CREATE TABLE sequence_copy AS SELECT name, seq FROM sqlite_sequence;
+DROP TABLE url_tags;
+DROP TABLE variants;
+DROP TABLE rules;
+DROP TABLE clicks;
+DROP TABLE urls;
+CREATE TABLE urls(
+    id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+    src TEXT NOT NULL,
+    num INTEGER NOT NULL,
+    active BOOLEAN NOT NULL,
+    created_at DATETIME NOT NULL,
+    updated_at DATETIME NOT NULL,
+    slug TEXT,
+    expires_at DATETIME,
+    fallback_url TEXT,
+    password TEXT,
+    redirect_type TEXT,
+    forward_query BOOLEAN NOT NULL DEFAULT FALSE,
+    forward_path BOOLEAN NOT NULL DEFAULT FALSE,
+    utm_source TEXT,
+    utm_medium TEXT,
+    utm_campaign TEXT,
+    utm_term TEXT,
+    utm_content TEXT,
+    utm_on_redirect BOOLEAN NOT NULL DEFAULT FALSE,
+    sticky_variant BOOLEAN NOT NULL DEFAULT FALSE,
+    title TEXT,
+    notes TEXT
+);
+CREATE UNIQUE INDEX urls_slug_idx ON urls(slug);
+CREATE UNIQUE INDEX urls_src_idx ON urls(src) WHERE utm_on_redirect = FALSE;
+CREATE INDEX urls_utm_campaign_idx ON urls(utm_campaign);
+INSERT INTO urls (id, src, num, active, created_at, updated_at, slug, expires_at, fallback_url,
+    password, redirect_type, forward_query, forward_path, utm_source, utm_medium,
+    utm_campaign, utm_term, utm_content, utm_on_redirect, sticky_variant, title, notes)
+    SELECT id, src, num, active, created_at, updated_at, slug, expires_at, fallback_url,
+    password, redirect_type, forward_query, forward_path, utm_source, utm_medium,
+    utm_campaign, utm_term, utm_content, utm_on_redirect, sticky_variant, title, notes
+    FROM urls_copy;
+CREATE TABLE clicks(
+    id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+    url_id INTEGER NOT NULL REFERENCES urls(id) ON DELETE CASCADE,
+    referrer TEXT,
+    user_agent TEXT,
+    accept_language TEXT,
+    client_hash TEXT NOT NULL,
+    created_at DATETIME NOT NULL,
+    rule_id INTEGER,
+    variant_id INTEGER
+);
+CREATE INDEX clicks_url_id_created_at_idx ON clicks(url_id, created_at);
+INSERT INTO clicks SELECT id, url_id, referrer, user_agent, accept_language, client_hash,
+    created_at, rule_id, variant_id FROM clicks_copy;
+CREATE TABLE rules(
+    id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+    url_id INTEGER NOT NULL REFERENCES urls(id) ON DELETE CASCADE,
+    position INTEGER NOT NULL,
+    os TEXT,
+    device TEXT,
+    bot BOOLEAN,
+    src TEXT NOT NULL,
+    created_at DATETIME NOT NULL
+);
+CREATE INDEX rules_url_id_idx ON rules(url_id, position);
+INSERT INTO rules SELECT id, url_id, position, os, device, bot, src, created_at
+    FROM rules_copy;
+CREATE TABLE variants(
+    id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+    url_id INTEGER NOT NULL REFERENCES urls(id) ON DELETE CASCADE,
+    src TEXT NOT NULL,
+    weight INTEGER NOT NULL DEFAULT 1,
+    created_at DATETIME NOT NULL
+);
+CREATE INDEX variants_url_id_idx ON variants(url_id);
+INSERT INTO variants SELECT id, url_id, src, weight, created_at FROM variants_copy;
+CREATE TABLE url_tags(
+    url_id INTEGER NOT NULL REFERENCES urls(id) ON DELETE CASCADE,
+    tag_id INTEGER NOT NULL REFERENCES tags(id) ON DELETE CASCADE,
+    PRIMARY KEY (url_id, tag_id)
+);
+CREATE INDEX url_tags_tag_id_idx ON url_tags(tag_id);
+INSERT INTO url_tags SELECT url_id, tag_id FROM url_tags_copy;
+-- Keep counting the ids from where they were, so the ones of deleted urls
+-- are never given again
+DELETE FROM sqlite_sequence WHERE name IN ('urls', 'clicks', 'rules', 'variants');
+INSERT INTO sqlite_sequence SELECT name, seq FROM sequence_copy
+    WHERE name IN ('urls', 'clicks', 'rules', 'variants');
+DROP TABLE urls_copy;
+DROP TABLE clicks_copy;
+DROP TABLE rules_copy;
+DROP TABLE variants_copy;
+DROP TABLE url_tags_copy;
+DROP TABLE sequence_copy;
+DROP TABLE IF EXISTS domains;
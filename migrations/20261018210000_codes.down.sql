@@ -0,0 +1,2 @@
+DROP INDEX IF EXISTS urls_code_idx;
+ALTER TABLE urls DROP COLUMN code;
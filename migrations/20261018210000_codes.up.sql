@@ -0,0 +1,3 @@
+-- Add up migration script here
+ALTER TABLE urls ADD COLUMN code TEXT;
+CREATE UNIQUE INDEX IF NOT EXISTS urls_code_idx ON urls(IFNULL(domain_id, 0), code);
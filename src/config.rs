@@ -2,6 +2,14 @@ use serde::{Serialize, Deserialize};
 use serde_yaml::Error;
 use std::process;
 
+use crate::{
+    model::{
+        slug::SlugStrategy,
+        url::RedirectType,
+    },
+    password,
+};
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Configuration{
     url: String,
@@ -10,7 +18,35 @@ pub struct Configuration{
     port: u16,
     username: String,
     password: String,
+    #[serde(default)]
+    public_creation: bool,
     per_page: i64,
+    #[serde(default)]
+    behind_proxy: bool,
+    hash_salt: Option<String>,
+    domain_policy: Option<String>,
+    #[serde(default)]
+    rate_limits: RateLimits,
+    #[serde(default)]
+    redirect_type: RedirectType,
+    #[serde(default)]
+    slug_strategy: SlugStrategy,
+}
+
+/// A token bucket: up to `burst` requests at once, refilled with
+/// `per_minute` requests every minute.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub struct RateLimit{
+    pub burst: u32,
+    pub per_minute: u32,
+}
+
+/// The limits of every group of routes, that has no limit when it isn't set.
+#[derive(Debug, Default, Serialize, Deserialize, Clone)]
+pub struct RateLimits{
+    pub create: Option<RateLimit>,
+    pub ping: Option<RateLimit>,
+    pub redirect: Option<RateLimit>,
 }
 
 impl Configuration {
@@ -20,6 +56,13 @@ impl Configuration {
     pub fn get_url(&self) -> &str{
         &self.url
     }
+    /// The short link of a path, on its domain or on the default one. Every
+    /// domain is served with the scheme of `url`, or https if it has none.
+    pub fn get_short_url(&self, domain: Option<&str>, short: &str) -> String{
+        let base = self.get_url().trim_end_matches('/');
+        let (scheme, default_host) = base.split_once("://").unwrap_or(("https", base));
+        format!("{}://{}/{}", scheme, domain.unwrap_or(default_host), short)
+    }
     pub fn get_log_level(&self) -> &str{
         &self.log_level
     }
@@ -35,27 +78,70 @@ impl Configuration {
     pub fn get_password(&self) -> &str{
         &self.password
     }
+    pub fn is_public_creation(&self) -> bool{
+        self.public_creation
+    }
+    pub fn is_behind_proxy(&self) -> bool{
+        self.behind_proxy
+    }
+    pub fn get_hash_salt(&self) -> Option<&str>{
+        self.hash_salt.as_deref()
+    }
+    pub fn get_domain_policy(&self) -> Option<&str>{
+        self.domain_policy.as_deref()
+    }
+    pub fn get_rate_limits(&self) -> &RateLimits{
+        &self.rate_limits
+    }
+    /// The redirect type of the urls that don't choose one.
+    pub fn get_redirect_type(&self) -> RedirectType{
+        self.redirect_type
+    }
     pub fn get_page(&self) -> i64{
         self.per_page
     }
+    pub fn get_slug_strategy(&self) -> &SlugStrategy{
+        &self.slug_strategy
+    }
 
     pub async fn read() -> Self{
         let content = match tokio::fs::read_to_string("config.yml")
             .await {
                 Ok(value) => value,
                 Err(e) => {
-                    println!("Error with config file `config.yml`: {}",
-                        e.to_string());
+                    println!("Error with config file `config.yml`: {}", e);
                     process::exit(0);
                 }
             };
         match Configuration::new(&content){
-            Ok(configuration) => configuration,
+            Ok(configuration) if !password::is_hash(&configuration.password) => {
+                println!("Error with config file `config.yml`: `password` must \
+                    be a hash. Get it with `shortrs hash <password>`");
+                process::exit(0);
+            },
+            Ok(configuration) => match configuration.slug_strategy.validate(){
+                Ok(()) => configuration,
+                Err(e) => {
+                    println!("Error with config file `config.yml`: {}", e);
+                    process::exit(0);
+                }
+            },
             Err(e) => {
-                println!("Error with config file `config.yml`: {}",
-                    e.to_string());
+                println!("Error with config file `config.yml`: {}", e);
                 process::exit(0);
             }
         }
     }
 }
+
+#[test]
+fn short_urls(){
+    let content = "url: http://localhost:8081/\nlog_level: debug\ndb_url: sqlite:urls.db\n\
+        port: 8081\nusername: usuario\npassword: secret\nper_page: 25";
+    let configuration = Configuration::new(content).unwrap();
+    assert!(configuration.get_short_url(None, "abc") == "http://localhost:8081/abc");
+    assert!(configuration.get_short_url(Some("brand.link"), "abc") == "http://brand.link/abc");
+    let configuration = Configuration::new(&content.replace("http://", "")).unwrap();
+    assert!(configuration.get_short_url(None, "abc") == "https://localhost:8081/abc");
+    assert!(configuration.get_short_url(Some("brand.link"), "abc") == "https://brand.link/abc");
+}
@@ -0,0 +1,476 @@
+use axum::{
+    Router,
+    Extension,
+    routing::{delete, get, patch},
+    response::{IntoResponse, Response},
+    http::StatusCode,
+    extract::{
+        Json,
+        Path,
+        Query,
+        rejection::JsonRejection,
+    },
+};
+use tracing::error;
+use serde::Serialize;
+use crate::model::{
+    url::{Url, Search, ShortUrl, UrlPatch},
+    rule::{NewRule, Rule},
+    variant::{NewVariant, Variant},
+    domain::{Domain, DomainPatch, NewDomain},
+    parameters::ListParameters,
+};
+
+use super::{
+    ApiContext,
+    LinkDomain,
+    RequireCreate,
+    RequireManage,
+    RequireStats,
+    json_error,
+    url::{NewUrl, check_destination, clean_text, create_url, hash_password},
+};
+
+const MAX_PER_PAGE: i64 = 100;
+
+
+pub fn router() -> Router{
+    Router::new()
+    .route("/api/v1/links",
+        get(list_links).post(create_link)
+    )
+    .route("/api/v1/links/:short",
+        get(get_link).patch(update_link).delete(delete_link)
+    )
+    .route("/api/v1/links/:short/rules",
+        get(list_rules).post(create_rule)
+    )
+    .route("/api/v1/links/:short/rules/:id",
+        delete(delete_rule)
+    )
+    .route("/api/v1/links/:short/variants",
+        get(list_variants).post(create_variant)
+    )
+    .route("/api/v1/links/:short/variants/:id",
+        delete(delete_variant)
+    )
+    .route("/api/v1/domains",
+        get(list_domains).post(create_domain)
+    )
+    .route("/api/v1/domains/:host",
+        patch(update_domain).delete(delete_domain)
+    )
+}
+
+#[derive(Serialize)]
+struct Page{
+    links: Vec<ShortUrl>,
+    page: i64,
+    per_page: i64,
+    total: i64,
+}
+
+fn not_found(short: &str) -> Response{
+    json_error(StatusCode::NOT_FOUND, "Link not found", short)
+}
+
+fn internal_error(short: &str, e: sqlx::Error) -> Response{
+    error!("Error with link {}. {}", short, e);
+    json_error(StatusCode::INTERNAL_SERVER_ERROR, &e.to_string(), short)
+}
+
+fn is_unique_violation(e: &sqlx::Error) -> bool{
+    matches!(e, sqlx::Error::Database(e) if e.message().contains("UNIQUE"))
+}
+
+fn is_foreign_key_violation(e: &sqlx::Error) -> bool{
+    matches!(e, sqlx::Error::Database(e) if e.message().contains("FOREIGN KEY"))
+}
+
+async fn read_link(ctx: &ApiContext, domain: &LinkDomain, short: &str) -> Result<Url, Response>{
+    match Url::read_from_short(&ctx.pool, domain.get_id(), short).await{
+        Ok(url) => Ok(url),
+        Err(sqlx::Error::RowNotFound) => Err(not_found(short)),
+        Err(e) => Err(internal_error(short, e)),
+    }
+}
+
+/// The links skipped before a page, if it doesn't overflow.
+fn page_offset(page: i64, per_page: i64) -> Option<i64>{
+    (page - 1).checked_mul(per_page)
+}
+
+async fn list_links(
+    _auth: RequireStats,
+    ctx: Extension<ApiContext>,
+    domain: LinkDomain,
+    Query(params): Query<ListParameters>,
+) -> impl IntoResponse{
+    let page = params.get_page();
+    let per_page = params.per_page
+        .unwrap_or_else(|| ctx.config.get_page())
+        .clamp(1, MAX_PER_PAGE);
+    let offset = match page_offset(page, per_page){
+        Some(offset) => offset,
+        None => return json_error(StatusCode::BAD_REQUEST, "The page is too large",
+            &page.to_string()),
+    };
+    let search = Search{
+        domain: Some(domain.get_id()),
+        ..params.get_search()
+    };
+    let result = async {
+        Ok::<_, sqlx::Error>((
+            Url::search(&ctx.pool, &search, per_page, offset).await?,
+            Url::count(&ctx.pool, &search).await?,
+        ))
+    }.await;
+    match result{
+        Ok((urls, total)) => Json(Page{
+            links: urls.iter().map(|url| url.get_short()).collect(),
+            page,
+            per_page,
+            total,
+        }).into_response(),
+        Err(e) => internal_error("", e),
+    }
+}
+
+async fn get_link(
+    _auth: RequireStats,
+    ctx: Extension<ApiContext>,
+    domain: LinkDomain,
+    Path(short): Path<String>,
+) -> impl IntoResponse{
+    match read_link(&ctx, &domain, &short).await{
+        Ok(url) => Json(url.get_short()).into_response(),
+        Err(response) => response,
+    }
+}
+
+async fn create_link(
+    _auth: RequireCreate,
+    ctx: Extension<ApiContext>,
+    domain: LinkDomain,
+    payload: Result<Json<NewUrl>, JsonRejection>,
+) -> impl IntoResponse{
+    let Json(payload) = match payload{
+        Ok(payload) => payload,
+        Err(rejection) => return json_error(rejection.status(), &rejection.body_text(), ""),
+    };
+    match create_url(&ctx, domain.get_id(), &payload.src, payload.options).await{
+        Ok(url) => (StatusCode::CREATED, Json(url.get_short())).into_response(),
+        Err((status, message)) => json_error(status, &message, &payload.src),
+    }
+}
+
+async fn update_link(
+    _auth: RequireManage,
+    ctx: Extension<ApiContext>,
+    domain: LinkDomain,
+    Path(short): Path<String>,
+    payload: Result<Json<UrlPatch>, JsonRejection>,
+) -> impl IntoResponse{
+    let Json(mut patch) = match payload{
+        Ok(payload) => payload,
+        Err(rejection) => return json_error(rejection.status(), &rejection.body_text(), &short),
+    };
+    if let Some(src) = patch.src.take(){
+        patch.src = match check_destination(&ctx, &src).await{
+            Ok(src) => Some(src),
+            Err((status, message)) => return json_error(status, &message, &short),
+        };
+    }
+    if let Some(fallback) = patch.fallback_url.take(){
+        patch.fallback_url = match fallback.filter(|fallback| !fallback.is_empty()){
+            Some(fallback) => match check_destination(&ctx, &fallback).await{
+                Ok(fallback) => Some(Some(fallback)),
+                Err((status, message)) => return json_error(status, &message, &short),
+            },
+            None => Some(None),
+        };
+    }
+    let mut url = match read_link(&ctx, &domain, &short).await{
+        Ok(url) => url,
+        Err(response) => return response,
+    };
+    // An empty password removes it, as `null` does
+    if let Some(password) = patch.password.take(){
+        patch.password = match password.filter(|password| !password.is_empty()){
+            Some(password) => match hash_password(password).await{
+                Ok(hash) => Some(Some(hash)),
+                Err((status, message)) => return json_error(status, &message, &short),
+            },
+            None => Some(None),
+        };
+    }
+    // An empty title or notes clear them too
+    patch.title = patch.title.map(clean_text);
+    patch.notes = patch.notes.map(clean_text);
+    url.apply(patch);
+    match Url::update(&ctx.pool, url).await{
+        Ok(url) => Json(url.get_short()).into_response(),
+        Err(e) if is_unique_violation(&e) => json_error(StatusCode::CONFLICT,
+            "That destination is already shortened", &short),
+        Err(e) => internal_error(&short, e),
+    }
+}
+
+async fn delete_link(
+    _auth: RequireManage,
+    ctx: Extension<ApiContext>,
+    domain: LinkDomain,
+    Path(short): Path<String>,
+) -> impl IntoResponse{
+    let url = match read_link(&ctx, &domain, &short).await{
+        Ok(url) => url,
+        Err(response) => return response,
+    };
+    match Url::delete(&ctx.pool, url.get_id()).await{
+        Ok(url) => Json(url.get_short()).into_response(),
+        Err(e) => internal_error(&short, e),
+    }
+}
+
+async fn list_rules(
+    _auth: RequireStats,
+    ctx: Extension<ApiContext>,
+    domain: LinkDomain,
+    Path(short): Path<String>,
+) -> impl IntoResponse{
+    let url = match read_link(&ctx, &domain, &short).await{
+        Ok(url) => url,
+        Err(response) => return response,
+    };
+    match Rule::read_for_url(&ctx.pool, url.get_id()).await{
+        Ok(rules) => Json(rules).into_response(),
+        Err(e) => internal_error(&short, e),
+    }
+}
+
+async fn create_rule(
+    _auth: RequireManage,
+    ctx: Extension<ApiContext>,
+    domain: LinkDomain,
+    Path(short): Path<String>,
+    payload: Result<Json<NewRule>, JsonRejection>,
+) -> impl IntoResponse{
+    let Json(mut rule) = match payload{
+        Ok(payload) => payload,
+        Err(rejection) => return json_error(rejection.status(), &rejection.body_text(), &short),
+    };
+    if !rule.has_conditions(){
+        return json_error(StatusCode::BAD_REQUEST,
+            "A rule needs an os, a device or bot to match", &short);
+    }
+    rule.src = match check_destination(&ctx, &rule.src).await{
+        Ok(src) => src,
+        Err((status, message)) => return json_error(status, &message, &short),
+    };
+    let url = match read_link(&ctx, &domain, &short).await{
+        Ok(url) => url,
+        Err(response) => return response,
+    };
+    match Rule::create(&ctx.pool, url.get_id(), &rule).await{
+        Ok(rule) => (StatusCode::CREATED, Json(rule)).into_response(),
+        Err(e) => internal_error(&short, e),
+    }
+}
+
+async fn delete_rule(
+    _auth: RequireManage,
+    ctx: Extension<ApiContext>,
+    domain: LinkDomain,
+    Path((short, id)): Path<(String, i64)>,
+) -> impl IntoResponse{
+    let url = match read_link(&ctx, &domain, &short).await{
+        Ok(url) => url,
+        Err(response) => return response,
+    };
+    match Rule::delete(&ctx.pool, url.get_id(), id).await{
+        Ok(rule) => Json(rule).into_response(),
+        Err(sqlx::Error::RowNotFound) => json_error(StatusCode::NOT_FOUND, "Rule not found",
+            &id.to_string()),
+        Err(e) => internal_error(&short, e),
+    }
+}
+
+async fn list_variants(
+    _auth: RequireStats,
+    ctx: Extension<ApiContext>,
+    domain: LinkDomain,
+    Path(short): Path<String>,
+) -> impl IntoResponse{
+    let url = match read_link(&ctx, &domain, &short).await{
+        Ok(url) => url,
+        Err(response) => return response,
+    };
+    match Variant::read_for_url(&ctx.pool, url.get_id()).await{
+        Ok(variants) => Json(variants).into_response(),
+        Err(e) => internal_error(&short, e),
+    }
+}
+
+async fn create_variant(
+    _auth: RequireManage,
+    ctx: Extension<ApiContext>,
+    domain: LinkDomain,
+    Path(short): Path<String>,
+    payload: Result<Json<NewVariant>, JsonRejection>,
+) -> impl IntoResponse{
+    let Json(mut variant) = match payload{
+        Ok(payload) => payload,
+        Err(rejection) => return json_error(rejection.status(), &rejection.body_text(), &short),
+    };
+    if variant.weight == 0{
+        return json_error(StatusCode::BAD_REQUEST, "The weight of a variant can't be 0", &short);
+    }
+    variant.src = match check_destination(&ctx, &variant.src).await{
+        Ok(src) => src,
+        Err((status, message)) => return json_error(status, &message, &short),
+    };
+    let url = match read_link(&ctx, &domain, &short).await{
+        Ok(url) => url,
+        Err(response) => return response,
+    };
+    match Variant::create(&ctx.pool, url.get_id(), &variant).await{
+        Ok(variant) => (StatusCode::CREATED, Json(variant)).into_response(),
+        Err(e) => internal_error(&short, e),
+    }
+}
+
+async fn delete_variant(
+    _auth: RequireManage,
+    ctx: Extension<ApiContext>,
+    domain: LinkDomain,
+    Path((short, id)): Path<(String, i64)>,
+) -> impl IntoResponse{
+    let url = match read_link(&ctx, &domain, &short).await{
+        Ok(url) => url,
+        Err(response) => return response,
+    };
+    match Variant::delete(&ctx.pool, url.get_id(), id).await{
+        Ok(variant) => Json(variant).into_response(),
+        Err(sqlx::Error::RowNotFound) => json_error(StatusCode::NOT_FOUND, "Variant not found",
+            &id.to_string()),
+        Err(e) => internal_error(&short, e),
+    }
+}
+
+async fn read_domain(ctx: &ApiContext, host: &str) -> Result<Domain, Response>{
+    let normalized = Domain::normalize(host).unwrap_or_default();
+    match Domain::read_from_host(&ctx.pool, &normalized).await{
+        Ok(domain) => Ok(domain),
+        Err(sqlx::Error::RowNotFound) => Err(json_error(StatusCode::NOT_FOUND,
+            "Domain not found", host)),
+        Err(e) => Err(internal_error(host, e)),
+    }
+}
+
+/// Accepts the root or not found page of a domain, leaving it out when it
+/// is empty.
+async fn check_page(ctx: &ApiContext, host: &str, page: Option<String>) -> Result<Option<String>, Response>{
+    match page.filter(|page| !page.is_empty()){
+        Some(page) => check_destination(ctx, &page)
+            .await
+            .map(Some)
+            .map_err(|(status, message)| json_error(status, &message, host)),
+        None => Ok(None),
+    }
+}
+
+async fn list_domains(
+    _auth: RequireStats,
+    ctx: Extension<ApiContext>,
+) -> impl IntoResponse{
+    match Domain::read_all(&ctx.pool).await{
+        Ok(domains) => Json(domains).into_response(),
+        Err(e) => internal_error("", e),
+    }
+}
+
+async fn create_domain(
+    _auth: RequireManage,
+    ctx: Extension<ApiContext>,
+    payload: Result<Json<NewDomain>, JsonRejection>,
+) -> impl IntoResponse{
+    let Json(mut domain) = match payload{
+        Ok(payload) => payload,
+        Err(rejection) => return json_error(rejection.status(), &rejection.body_text(), ""),
+    };
+    domain.host = match Domain::normalize(&domain.host){
+        Some(host) => host,
+        None => return json_error(StatusCode::BAD_REQUEST,
+            &format!("The host '{}' is not valid", domain.host), &domain.host),
+    };
+    domain.root_url = match check_page(&ctx, &domain.host, domain.root_url.take()).await{
+        Ok(page) => page,
+        Err(response) => return response,
+    };
+    domain.not_found_url = match check_page(&ctx, &domain.host, domain.not_found_url.take()).await{
+        Ok(page) => page,
+        Err(response) => return response,
+    };
+    match Domain::create(&ctx.pool, &domain).await{
+        Ok(domain) => (StatusCode::CREATED, Json(domain)).into_response(),
+        Err(e) if is_unique_violation(&e) => json_error(StatusCode::CONFLICT,
+            "That domain is already served", &domain.host),
+        Err(e) => internal_error(&domain.host, e),
+    }
+}
+
+async fn update_domain(
+    _auth: RequireManage,
+    ctx: Extension<ApiContext>,
+    Path(host): Path<String>,
+    payload: Result<Json<DomainPatch>, JsonRejection>,
+) -> impl IntoResponse{
+    let Json(mut patch) = match payload{
+        Ok(payload) => payload,
+        Err(rejection) => return json_error(rejection.status(), &rejection.body_text(), &host),
+    };
+    let domain = match read_domain(&ctx, &host).await{
+        Ok(domain) => domain,
+        Err(response) => return response,
+    };
+    if let Some(page) = patch.root_url.take(){
+        patch.root_url = match check_page(&ctx, &host, page).await{
+            Ok(page) => Some(page),
+            Err(response) => return response,
+        };
+    }
+    if let Some(page) = patch.not_found_url.take(){
+        patch.not_found_url = match check_page(&ctx, &host, page).await{
+            Ok(page) => Some(page),
+            Err(response) => return response,
+        };
+    }
+    match Domain::update(&ctx.pool, domain.get_id(), patch).await{
+        Ok(domain) => Json(domain).into_response(),
+        Err(e) => internal_error(&host, e),
+    }
+}
+
+async fn delete_domain(
+    _auth: RequireManage,
+    ctx: Extension<ApiContext>,
+    Path(host): Path<String>,
+) -> impl IntoResponse{
+    let domain = match read_domain(&ctx, &host).await{
+        Ok(domain) => domain,
+        Err(response) => return response,
+    };
+    match Domain::delete(&ctx.pool, domain.get_id()).await{
+        Ok(domain) => Json(domain).into_response(),
+        Err(e) if is_foreign_key_violation(&e) => json_error(StatusCode::CONFLICT,
+            "The domain still has links", &host),
+        Err(e) => internal_error(&host, e),
+    }
+}
+
+#[test]
+fn offset_of_huge_page(){
+    assert!(page_offset(1, 25) == Some(0));
+    assert!(page_offset(3, 25) == Some(50));
+    assert!(page_offset(i64::MAX, MAX_PER_PAGE).is_none());
+}
@@ -0,0 +1,60 @@
+use std::{
+    collections::HashMap,
+    hash::Hash,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+const MAX_ATTEMPTS: u32 = 5;
+const WINDOW: Duration = Duration::from_secs(15 * 60);
+
+
+/// Counts the failed attempts of every client, blocking it for the rest of
+/// the window once it reaches `MAX_ATTEMPTS`.
+pub struct Attempts<K>{
+    failures: Mutex<HashMap<K, (u32, Instant)>>,
+}
+
+impl<K: Eq + Hash> Attempts<K>{
+    pub fn new() -> Self{
+        Self{
+            failures: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// How long the client has to wait before trying again, if it is
+    /// blocked.
+    pub fn retry_after(&self, key: &K) -> Option<Duration>{
+        let failures = self.failures.lock().unwrap();
+        match failures.get(key){
+            Some((count, since)) if *count >= MAX_ATTEMPTS => WINDOW.checked_sub(since.elapsed()),
+            _ => None,
+        }
+    }
+
+    pub fn fail(&self, key: K){
+        let mut failures = self.failures.lock().unwrap();
+        failures.retain(|_, (_, since)| since.elapsed() < WINDOW);
+        failures.entry(key)
+            .or_insert((0, Instant::now()))
+            .0 += 1;
+    }
+
+    pub fn clear(&self, key: &K){
+        self.failures.lock().unwrap().remove(key);
+    }
+}
+
+#[test]
+fn blocks_after_max_attempts(){
+    let attempts = Attempts::new();
+    for _ in 1..MAX_ATTEMPTS{
+        attempts.fail("client");
+    }
+    assert!(attempts.retry_after(&"client").is_none());
+    attempts.fail("client");
+    assert!(attempts.retry_after(&"client").is_some());
+    assert!(attempts.retry_after(&"other").is_none());
+    attempts.clear(&"client");
+    assert!(attempts.retry_after(&"client").is_none());
+}
@@ -0,0 +1,89 @@
+use axum::{
+    Router,
+    Extension,
+    routing::get,
+    response::{IntoResponse, Html, Response},
+    http::StatusCode,
+    extract::{Json, Path, Query},
+};
+use chrono::{DateTime, Utc};
+use tera::{Tera, Context};
+use serde::Serialize;
+use crate::model::{
+    url::Url,
+    parameters::FormatParameters,
+};
+
+use super::{ApiContext, LinkDomain};
+
+
+pub fn router() -> Router{
+    Router::new()
+    .route("/_info/:path",
+        get(get_info)
+    )
+}
+
+/// What anyone can know about a link before visiting it. The destination
+/// of a protected link is kept hidden.
+#[derive(Serialize)]
+struct LinkInfo{
+    short: String,
+    url: String,
+    src: Option<String>,
+    clicks: u32,
+    active: bool,
+    expired: bool,
+    blocked: bool,
+    protected: bool,
+    created_at: DateTime<Utc>,
+}
+
+impl LinkInfo{
+    fn new(ctx: &ApiContext, url: &Url) -> Self{
+        let short = url.get_url();
+        Self{
+            url: ctx.config.get_short_url(url.get_domain(), &short),
+            short,
+            src: if url.is_protected() { None } else { Some(url.get_src().to_string()) },
+            clicks: url.get_num(),
+            active: url.is_active(),
+            expired: url.is_expired(),
+            blocked: !ctx.policy.read().unwrap().allows_url(url.get_src()),
+            protected: url.is_protected(),
+            created_at: url.get_created_at(),
+        }
+    }
+}
+
+/// Shows a link instead of following it, so it doesn't count as a visit.
+pub(super) async fn preview(ctx: &ApiContext, t: &Tera, domain_id: Option<i64>, path: &str,
+        json: bool) -> Response{
+    let url = match Url::read_from_short(&ctx.pool, domain_id, path).await{
+        Ok(url) => url,
+        Err(_) if json => return StatusCode::NOT_FOUND.into_response(),
+        Err(_) => {
+            let mut context = Context::new();
+            context.insert("error", &format!("There is no link '{}'", path));
+            return (StatusCode::NOT_FOUND, Html(t.render("error.html", &context).unwrap()))
+                .into_response();
+        }
+    };
+    let info = LinkInfo::new(ctx, &url);
+    if json{
+        return Json(info).into_response();
+    }
+    let mut context = Context::new();
+    context.insert("info", &info);
+    Html(t.render("info.html", &context).unwrap()).into_response()
+}
+
+async fn get_info(
+    ctx: Extension<ApiContext>,
+    t: Extension<Tera>,
+    domain: LinkDomain,
+    Path(path): Path<String>,
+    Query(params): Query<FormatParameters>,
+) -> impl IntoResponse{
+    preview(&ctx, &t, domain.get_id(), &path, params.format.as_deref() == Some("json")).await
+}
@@ -0,0 +1,84 @@
+use axum::{
+    Router,
+    Extension,
+    routing::{get, delete},
+    response::IntoResponse,
+    http::StatusCode,
+    extract::{
+        Json,
+        Path,
+        rejection::JsonRejection,
+    },
+};
+use tracing::error;
+use serde::Deserialize;
+use crate::model::api_key::{ApiKey, Scope};
+
+use super::{ApiContext, RequireManage, json_error};
+
+
+pub fn router() -> Router{
+    Router::new()
+    .route("/_keys",
+        get(get_keys).post(post_key)
+    )
+    .route("/_keys/:id",
+        delete(revoke_key)
+    )
+}
+
+#[derive(Deserialize)]
+struct NewKey{
+    name: String,
+    scopes: Vec<Scope>,
+}
+
+async fn get_keys(
+    _auth: RequireManage,
+    ctx: Extension<ApiContext>,
+) -> impl IntoResponse{
+    match ApiKey::read_all(&ctx.pool).await{
+        Ok(keys) => Json(keys).into_response(),
+        Err(e) => {
+            error!("Can't read keys. {}", e);
+            json_error(StatusCode::INTERNAL_SERVER_ERROR, &e.to_string(), "")
+        }
+    }
+}
+
+async fn post_key(
+    _auth: RequireManage,
+    ctx: Extension<ApiContext>,
+    payload: Result<Json<NewKey>, JsonRejection>,
+) -> impl IntoResponse{
+    let Json(payload) = match payload{
+        Ok(payload) => payload,
+        Err(rejection) => return json_error(rejection.status(), &rejection.body_text(), ""),
+    };
+    if payload.name.is_empty() || payload.scopes.is_empty(){
+        return json_error(StatusCode::BAD_REQUEST, "A key needs a name and scopes", &payload.name);
+    }
+    match ApiKey::create(&ctx.pool, &payload.name, &payload.scopes).await{
+        Ok(new_key) => (StatusCode::CREATED, Json(new_key)).into_response(),
+        Err(e) => {
+            error!("Can't create key {}. {}", payload.name, e);
+            json_error(StatusCode::INTERNAL_SERVER_ERROR, &e.to_string(), &payload.name)
+        }
+    }
+}
+
+async fn revoke_key(
+    _auth: RequireManage,
+    ctx: Extension<ApiContext>,
+    Path(id): Path<i64>,
+) -> impl IntoResponse{
+    match ApiKey::revoke(&ctx.pool, id).await{
+        Ok(api_key) => Json(api_key).into_response(),
+        Err(sqlx::Error::RowNotFound) => json_error(StatusCode::NOT_FOUND, "Key not found",
+            &id.to_string()),
+        Err(e) => {
+            error!("Can't revoke key {}. {}", id, e);
+            json_error(StatusCode::INTERNAL_SERVER_ERROR, &e.to_string(), &id.to_string())
+        }
+    }
+}
@@ -0,0 +1,257 @@
+use std::{
+    collections::HashMap,
+    convert::Infallible,
+    net::IpAddr,
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+    time::{Duration, Instant},
+};
+use axum::{
+    http::{header, Method, Request, StatusCode},
+    response::{IntoResponse, Response},
+};
+use futures::future::BoxFuture;
+use sha2::{Digest, Sha256};
+use tower::{Layer, Service};
+use tracing::debug;
+use crate::config::{RateLimit, RateLimits};
+
+use super::{client_ip, json_error};
+
+/// Buckets kept before the full ones, that are as good as new, are dropped
+const MAX_BUCKETS: usize = 10_000;
+
+
+/// The groups of routes that are limited, each one with its own buckets.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum Group{
+    Create,
+    Ping,
+    Redirect,
+}
+
+impl Group{
+    fn of(method: &Method, path: &str) -> Option<Self>{
+        match (method, path){
+            (&Method::POST, "/" | "/api/v1/links" | "/_import") => Some(Group::Create),
+            (&Method::POST, "/_ping") => Some(Group::Ping),
+            (&Method::GET | &Method::POST, path) => {
+                let short = path.strip_prefix('/')?.split('/').next()?;
+                // The QR codes of a link count as visits, as drawing them
+                // is as costly
+                (!short.is_empty() && !short.starts_with('_') && short != "favicon.ico"
+                    && short != "assets" && short != "api")
+                    .then(|| Group::Redirect)
+            },
+            _ => None,
+        }
+    }
+
+    fn limit(self, limits: &RateLimits) -> Option<RateLimit>{
+        match self{
+            Group::Create => limits.create,
+            Group::Ping => limits.ping,
+            Group::Redirect => limits.redirect,
+        }
+    }
+}
+
+/// Who is limited: the address of the client and, apart, every credential
+/// sent in `Authorization`, that is kept only as a hash.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum Client{
+    Ip(IpAddr),
+    Credential(Vec<u8>),
+}
+
+struct Bucket{
+    tokens: f64,
+    updated: Instant,
+}
+
+impl Bucket{
+    fn refill(&mut self, limit: RateLimit, now: Instant){
+        let rate = limit.per_minute as f64 / 60.0;
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * rate).min(limit.burst as f64);
+        self.updated = now;
+    }
+}
+
+/// Token buckets for every group of routes and client.
+struct Limiter{
+    limits: RateLimits,
+    buckets: Mutex<HashMap<(Group, Client), Bucket>>,
+}
+
+impl Limiter{
+    fn new(limits: RateLimits) -> Self{
+        Self{
+            limits,
+            buckets: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Takes a token from the bucket of every client, or says how long to
+    /// wait for one when any of them is empty.
+    fn take(&self, group: Group, clients: &[Client], now: Instant) -> Result<(), Duration>{
+        let limit = match group.limit(&self.limits){
+            Some(limit) => limit,
+            None => return Ok(()),
+        };
+        let mut buckets = self.buckets.lock().unwrap();
+        if buckets.len() > MAX_BUCKETS{
+            buckets.retain(|(group, _), bucket| match group.limit(&self.limits){
+                Some(limit) => {
+                    bucket.refill(limit, now);
+                    bucket.tokens < limit.burst as f64
+                },
+                None => false,
+            });
+        }
+        let mut wait = Duration::ZERO;
+        for client in clients{
+            let bucket = buckets.entry((group, client.clone())).or_insert(Bucket{
+                tokens: limit.burst as f64,
+                updated: now,
+            });
+            bucket.refill(limit, now);
+            if bucket.tokens < 1.0{
+                let rate = limit.per_minute.max(1) as f64 / 60.0;
+                wait = wait.max(Duration::from_secs_f64((1.0 - bucket.tokens) / rate));
+            }
+        }
+        if !wait.is_zero(){
+            return Err(wait);
+        }
+        for client in clients{
+            if let Some(bucket) = buckets.get_mut(&(group, client.clone())){
+                bucket.tokens -= 1.0;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Limits the requests to create links, to ping and to follow links with
+/// the `rate_limits` of `config.yml`. Excess requests get a `429`.
+#[derive(Clone)]
+pub struct RateLimitLayer{
+    limiter: Arc<Limiter>,
+    behind_proxy: bool,
+}
+
+impl RateLimitLayer{
+    pub fn new(limits: RateLimits, behind_proxy: bool) -> Self{
+        Self{
+            limiter: Arc::new(Limiter::new(limits)),
+            behind_proxy,
+        }
+    }
+}
+
+impl<S> Layer<S> for RateLimitLayer{
+    type Service = RateLimited<S>;
+
+    fn layer(&self, inner: S) -> Self::Service{
+        RateLimited{
+            inner,
+            layer: self.clone(),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct RateLimited<S>{
+    inner: S,
+    layer: RateLimitLayer,
+}
+
+impl<S> RateLimited<S>{
+    fn check<B>(&self, request: &Request<B>) -> Result<(), Duration>{
+        let group = match Group::of(request.method(), request.uri().path()){
+            Some(group) => group,
+            None => return Ok(()),
+        };
+        let mut clients = Vec::with_capacity(2);
+        if let Some(ip) = client_ip(self.layer.behind_proxy, request.headers(), request.extensions()){
+            clients.push(Client::Ip(ip));
+        }
+        if let Some(credential) = request.headers().get(header::AUTHORIZATION){
+            clients.push(Client::Credential(Sha256::digest(credential.as_bytes()).to_vec()));
+        }
+        self.layer.limiter.take(group, &clients, Instant::now()).map_err(|wait| {
+            debug!("Rate limit of {:?} reached by {:?}", group, clients.first());
+            wait
+        })
+    }
+}
+
+impl<S, B> Service<Request<B>> for RateLimited<S>
+where
+    S: Service<Request<B>, Response = Response, Error = Infallible>,
+    S::Future: Send + 'static,
+{
+    type Response = Response;
+    type Error = Infallible;
+    type Future = BoxFuture<'static, Result<Response, Infallible>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>>{
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: Request<B>) -> Self::Future{
+        if let Err(wait) = self.check(&request){
+            // Rounded up, as `Retry-After` is in whole seconds
+            let seconds = wait.as_secs() + u64::from(wait.subsec_nanos() > 0);
+            let response = (
+                [(header::RETRY_AFTER, seconds.to_string())],
+                json_error(StatusCode::TOO_MANY_REQUESTS, "Too many requests",
+                    &format!("Retry in {} seconds", seconds)),
+            ).into_response();
+            return Box::pin(async move { Ok(response) });
+        }
+        Box::pin(self.inner.call(request))
+    }
+}
+
+#[test]
+fn classify_routes(){
+    assert!(Group::of(&Method::POST, "/") == Some(Group::Create));
+    assert!(Group::of(&Method::POST, "/api/v1/links") == Some(Group::Create));
+    assert!(Group::of(&Method::POST, "/_ping") == Some(Group::Ping));
+    assert!(Group::of(&Method::GET, "/abc") == Some(Group::Redirect));
+    assert!(Group::of(&Method::POST, "/abc") == Some(Group::Redirect));
+    assert!(Group::of(&Method::GET, "/gh/rust-lang/rust") == Some(Group::Redirect));
+    assert!(Group::of(&Method::GET, "/").is_none());
+    assert!(Group::of(&Method::GET, "/_stats").is_none());
+    assert!(Group::of(&Method::GET, "/abc/qr.svg") == Some(Group::Redirect));
+    assert!(Group::of(&Method::GET, "/abc/qr.png") == Some(Group::Redirect));
+    assert!(Group::of(&Method::GET, "/favicon.ico").is_none());
+    assert!(Group::of(&Method::GET, "/assets/style.css").is_none());
+    assert!(Group::of(&Method::GET, "/api/v1/links").is_none());
+    assert!(Group::of(&Method::GET, "/api/v1/links/abc").is_none());
+    assert!(Group::of(&Method::GET, "/api/v1/domains").is_none());
+    assert!(Group::of(&Method::POST, "/api/v1/domains").is_none());
+    assert!(Group::of(&Method::POST, "/api/v1/links/abc/rules").is_none());
+    assert!(Group::of(&Method::POST, "/api/v1/links/abc/variants").is_none());
+}
+
+#[test]
+fn refill_buckets(){
+    let limiter = Limiter::new(RateLimits{
+        create: Some(RateLimit{burst: 2, per_minute: 60}),
+        ..Default::default()
+    });
+    let now = Instant::now();
+    let ip = [Client::Ip("10.0.0.1".parse().unwrap())];
+    let key = [Client::Credential(vec![1])];
+    let both = [ip[0].clone(), key[0].clone()];
+    assert!(limiter.take(Group::Create, &both, now).is_ok());
+    assert!(limiter.take(Group::Create, &ip, now).is_ok());
+    // The address is empty, the credential is not but isn't spent either
+    assert!(limiter.take(Group::Create, &both, now) == Err(Duration::from_secs(1)));
+    assert!(limiter.take(Group::Create, &key, now).is_ok());
+    assert!(limiter.take(Group::Create, &ip, now + Duration::from_secs(1)).is_ok());
+    assert!(limiter.take(Group::Ping, &ip, now).is_ok());
+}
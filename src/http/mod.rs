@@ -1,24 +1,49 @@
-use std::{sync::Arc, net::{SocketAddr, Ipv4Addr}};
+use std::{sync::Arc, marker::PhantomData, net::{SocketAddr, IpAddr, Ipv4Addr}};
 use sqlx::SqlitePool;
 use axum::{
     Router,
-    extract::FromRequestParts,
-    middleware::from_extractor,
+    extract::{ConnectInfo, FromRequestParts, Query},
     http::{
         header,
+        Extensions,
+        HeaderMap,
         StatusCode,
         request::Parts,
     },
+    response::{IntoResponse, Response},
     Extension,
+    Json,
     RequestPartsExt,
 };
 use async_trait::async_trait;
-use crate::config::Configuration;
+use crate::{
+    config::Configuration,
+    model::{
+        api_key::{ApiKey, Scope},
+        domain::Domain,
+        response::Response as JsonResponse,
+    },
+    password,
+    policy::SharedPolicy,
+};
+use serde::Deserialize;
+use tracing::{debug, error};
+use rand::{distributions::Alphanumeric, Rng};
 use tower_http::trace::TraceLayer;
 use tower::ServiceBuilder;
 use tera::Tera;
+use attempts::Attempts;
+use limit::RateLimitLayer;
 
 mod url;
+mod attempts;
+mod limit;
+mod api;
+mod key;
+mod stats;
+mod transfer;
+mod qr;
+mod info;
 mod tatic;
 
 
@@ -26,9 +51,12 @@ mod tatic;
 struct ApiContext {
     config: Arc<Configuration>,
     pool: SqlitePool,
+    hash_salt: Arc<String>,
+    attempts: Arc<Attempts<(IpAddr, i64)>>,
+    policy: SharedPolicy,
 }
 
-pub async fn serve(config: Configuration, pool: SqlitePool) -> anyhow::Result<()> {
+pub async fn serve(config: Configuration, pool: SqlitePool, policy: SharedPolicy) -> anyhow::Result<()> {
 
     let tera = match Tera::new("templates/**/*.html") {
         Ok(t) => t,
@@ -37,29 +65,46 @@ pub async fn serve(config: Configuration, pool: SqlitePool) -> anyhow::Result<()
             ::std::process::exit(1);
         }
     };
+    let hash_salt = match config.get_hash_salt() {
+        Some(hash_salt) => hash_salt.to_string(),
+        None => rand::thread_rng()
+            .sample_iter(&Alphanumeric)
+            .take(32)
+            .map(char::from)
+            .collect(),
+    };
     let app = api_router().layer(
 
     ServiceBuilder::new()
         .layer(Extension(ApiContext {
             config: Arc::new(config.clone()),
             pool,
+            hash_salt: Arc::new(hash_salt),
+            attempts: Arc::new(Attempts::new()),
+            policy,
         }))
         // Enables logging. Use `RUST_LOG=tower_http=debug`
         .layer(TraceLayer::new_for_http())
-        //.layer(from_extractor::<RequireAuth>())
+        .layer(RateLimitLayer::new(config.get_rate_limits().clone(), config.is_behind_proxy()))
         .layer(Extension(tera))
 
     );
 
     axum::Server::bind(
         &SocketAddr::new(std::net::IpAddr::V4(Ipv4Addr::new(0, 0, 0, 0)), config.get_port()))
-        .serve(app.into_make_service())
+        .serve(app.into_make_service_with_connect_info::<SocketAddr>())
         .await
         .map_err(|_err| anyhow::anyhow!("Can't init"))
     
 }
 fn api_router() -> Router {
     url::router()
+        .merge(api::router())
+        .merge(key::router())
+        .merge(stats::router())
+        .merge(transfer::router())
+        .merge(qr::router())
+        .merge(info::router())
         .merge(tatic::router())
 }
 
@@ -70,42 +115,235 @@ fn api_router() -> Router {
 //        .merge(estatic::router())
 //        .merge(root::router())
 //}
-//
-//struct RequireAuth;
-//
-//#[async_trait]
-//impl<S> FromRequestParts<S> for RequireAuth
-//where
-//    S: Send + Sync,
-//{
-//    type Rejection = StatusCode;
-//
-//    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
-//        let Extension(ctx)= parts.extract::<Extension<ApiContext>>()
-//            .await
-//            .unwrap();
-//        if ctx.config.is_with_authentication(){
-//            let auth_header = parts
-//                .headers
-//                .get(header::AUTHORIZATION)
-//                .and_then(|value| value.to_str().ok());
-//            match auth_header {
-//                Some(auth_header) if token_is_valid(&ctx, auth_header) => {
-//                    Ok(Self)
-//                }
-//                _ => Err(StatusCode::UNAUTHORIZED),
-//            }
-//        }else{
-//            Ok(Self)
-//        }
-//    }
-//}
-//
-//fn token_is_valid(ctx: &ApiContext, auth_header: &str) -> bool {
-//    let base = format!("{}:{}", ctx.config.get_username(),
-//        ctx.config.get_password());
-//    let token = format!("Basic {}", base64::encode(base));
-//    auth_header == token
-//}
-//
-//
+
+/// Binds an extractor to the scope it requires, see `RequireScope`.
+pub trait ScopeMarker {
+    const SCOPE: Scope;
+}
+
+pub struct CreateScope;
+pub struct StatsScope;
+pub struct ManageScope;
+
+impl ScopeMarker for CreateScope {
+    const SCOPE: Scope = Scope::Create;
+}
+impl ScopeMarker for StatsScope {
+    const SCOPE: Scope = Scope::ReadStats;
+}
+impl ScopeMarker for ManageScope {
+    const SCOPE: Scope = Scope::Manage;
+}
+
+/// Accepts the admin credentials from `config.yml` through basic auth, that
+/// grant every scope, or an api key with the required scope as a bearer
+/// token. Creation is open to anyone when `public_creation` is enabled.
+pub struct RequireScope<S>(PhantomData<S>);
+
+pub type RequireCreate = RequireScope<CreateScope>;
+pub type RequireStats = RequireScope<StatsScope>;
+pub type RequireManage = RequireScope<ManageScope>;
+
+#[async_trait]
+impl<S, T> FromRequestParts<T> for RequireScope<S>
+where
+    S: ScopeMarker + Send,
+    T: Send + Sync,
+{
+    type Rejection = Response;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &T) -> Result<Self, Self::Rejection> {
+        let Extension(ctx)= parts.extract::<Extension<ApiContext>>()
+            .await
+            .map_err(IntoResponse::into_response)?;
+        if S::SCOPE == Scope::Create && ctx.config.is_public_creation() {
+            return Ok(Self(PhantomData));
+        }
+        let auth_header = parts
+            .headers
+            .get(header::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok());
+        match auth_header.and_then(|value| value.strip_prefix("Bearer ")) {
+            Some(key) => match ApiKey::authenticate(&ctx.pool, key).await {
+                Ok(api_key) if api_key.has_scope(S::SCOPE) => Ok(Self(PhantomData)),
+                Ok(api_key) => {
+                    debug!("Key {} lacks scope {:?}", api_key.get_name(), S::SCOPE);
+                    Err(json_error(StatusCode::FORBIDDEN, "Missing scope",
+                        &format!("{:?}", S::SCOPE)))
+                },
+                Err(_) => Err(unauthorized()),
+            },
+            None => match auth_header {
+                Some(auth_header) if token_is_valid(&ctx, auth_header).await => {
+                    Ok(Self(PhantomData))
+                }
+                _ => Err(unauthorized()),
+            },
+        }
+    }
+}
+
+async fn token_is_valid(ctx: &ApiContext, auth_header: &str) -> bool {
+    let credentials = auth_header
+        .strip_prefix("Basic ")
+        .and_then(|token| base64::decode(token).ok())
+        .and_then(|token| String::from_utf8(token).ok());
+    let (username, password) = match credentials.as_deref()
+            .and_then(|value| value.split_once(':')) {
+        Some((username, password)) => (username.to_string(), password.to_string()),
+        None => return false,
+    };
+    if username != ctx.config.get_username() {
+        return false;
+    }
+    // argon2 is slow by design, keep it away from the async workers
+    let hash = ctx.config.get_password().to_string();
+    tokio::task::spawn_blocking(move || password::verify(&password, &hash))
+        .await
+        .unwrap_or(false)
+}
+
+fn unauthorized() -> Response {
+    (
+        [(header::WWW_AUTHENTICATE, "Basic realm=\"shortrs\"")],
+        json_error(StatusCode::UNAUTHORIZED, "Unauthorized", ""),
+    ).into_response()
+}
+
+/// Error body shared by the JSON endpoints.
+fn json_error(status: StatusCode, message: &str, value: &str) -> Response {
+    (status, Json(JsonResponse::new(status.as_u16(), message, value))).into_response()
+}
+
+/// The address of the client, from `X-Forwarded-For` when `behind_proxy` is
+/// enabled or from the connection otherwise. Only the rightmost entry of the
+/// header is trusted, the one the proxy appends; the others come from the
+/// client.
+pub struct ClientIp(pub IpAddr);
+
+#[async_trait]
+impl<S> FromRequestParts<S> for ClientIp
+where
+    S: Send + Sync,
+{
+    type Rejection = Response;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let Extension(ctx)= parts.extract::<Extension<ApiContext>>()
+            .await
+            .map_err(IntoResponse::into_response)?;
+        client_ip(ctx.config.is_behind_proxy(), &parts.headers, &parts.extensions)
+            .map(Self)
+            .ok_or_else(|| StatusCode::INTERNAL_SERVER_ERROR.into_response())
+    }
+}
+
+fn client_ip(behind_proxy: bool, headers: &HeaderMap, extensions: &Extensions) -> Option<IpAddr> {
+    if behind_proxy {
+        let forwarded = headers
+            .get("x-forwarded-for")
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.rsplit(',').next())
+            .and_then(|value| value.trim().parse().ok());
+        if forwarded.is_some() {
+            return forwarded;
+        }
+    }
+    extensions
+        .get::<ConnectInfo<SocketAddr>>()
+        .map(|ConnectInfo(addr)| addr.ip())
+}
+
+/// The domain a request was sent to, from `X-Forwarded-Host` when
+/// `behind_proxy` is enabled or from `Host` otherwise. It is `None` for the
+/// default domain, the one of `url` in `config.yml`, and for any other host
+/// that isn't served.
+pub struct HostDomain(pub Option<Domain>);
+
+#[async_trait]
+impl<S> FromRequestParts<S> for HostDomain
+where
+    S: Send + Sync,
+{
+    type Rejection = Response;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let Extension(ctx)= parts.extract::<Extension<ApiContext>>()
+            .await
+            .map_err(IntoResponse::into_response)?;
+        let host = match request_host(ctx.config.is_behind_proxy(), &parts.headers) {
+            Some(host) => host,
+            None => return Ok(Self(None)),
+        };
+        match Domain::read_from_host(&ctx.pool, &host).await {
+            Ok(domain) => Ok(Self(Some(domain))),
+            Err(sqlx::Error::RowNotFound) => Ok(Self(None)),
+            Err(e) => {
+                error!("Can't read domain {}. {}", host, e);
+                Err(StatusCode::INTERNAL_SERVER_ERROR.into_response())
+            }
+        }
+    }
+}
+
+fn request_host(behind_proxy: bool, headers: &HeaderMap) -> Option<String> {
+    let forwarded = if behind_proxy {
+        headers.get("x-forwarded-host")
+    } else {
+        None
+    };
+    forwarded
+        .or_else(|| headers.get(header::HOST))
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.rsplit(',').next())
+        .and_then(Domain::normalize)
+}
+
+#[derive(Deserialize)]
+struct DomainParameter {
+    domain: Option<String>,
+}
+
+/// The domain of the links a request reads or manages, given as `?domain=`
+/// or else the one the request was sent to, as in `HostDomain`.
+pub struct LinkDomain(pub Option<Domain>);
+
+impl LinkDomain {
+    pub fn get_id(&self) -> Option<i64> {
+        self.0.as_ref().map(Domain::get_id)
+    }
+}
+
+#[async_trait]
+impl<S> FromRequestParts<S> for LinkDomain
+where
+    S: Send + Sync,
+{
+    type Rejection = Response;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let host = Query::<DomainParameter>::try_from_uri(&parts.uri)
+            .ok()
+            .and_then(|Query(param)| param.domain)
+            .filter(|host| !host.is_empty());
+        let host = match host {
+            Some(host) => host,
+            None => return HostDomain::from_request_parts(parts, state)
+                .await
+                .map(|HostDomain(domain)| Self(domain)),
+        };
+        let Extension(ctx)= parts.extract::<Extension<ApiContext>>()
+            .await
+            .map_err(IntoResponse::into_response)?;
+        let normalized = Domain::normalize(&host).unwrap_or_default();
+        match Domain::read_from_host(&ctx.pool, &normalized).await {
+            Ok(domain) => Ok(Self(Some(domain))),
+            Err(sqlx::Error::RowNotFound) => Err(json_error(StatusCode::NOT_FOUND,
+                "Domain not found", &host)),
+            Err(e) => {
+                error!("Can't read domain {}. {}", host, e);
+                Err(StatusCode::INTERNAL_SERVER_ERROR.into_response())
+            }
+        }
+    }
+}
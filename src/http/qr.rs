@@ -0,0 +1,172 @@
+use axum::{
+    Router,
+    Extension,
+    routing::get,
+    response::{IntoResponse, Response},
+    http::{StatusCode, header},
+    extract::{Path, Query},
+};
+use qrcode::{Color, EcLevel, QrCode};
+use tracing::error;
+use crate::model::{
+    url::Url,
+    parameters::{QrCorrection, QrParameters},
+};
+
+use super::{ApiContext, LinkDomain};
+
+const DEFAULT_SIZE: u32 = 256;
+const MAX_SIZE: u32 = 1024;
+const DEFAULT_MARGIN: u32 = 4;
+const MAX_MARGIN: u32 = 16;
+
+
+pub fn router() -> Router{
+    Router::new()
+    .route("/:path/qr.svg",
+        get(qr_svg)
+    )
+    .route("/:path/qr.png",
+        get(qr_png)
+    )
+}
+
+impl From<QrCorrection> for EcLevel{
+    fn from(correction: QrCorrection) -> Self{
+        match correction{
+            QrCorrection::Low => EcLevel::L,
+            QrCorrection::Medium => EcLevel::M,
+            QrCorrection::Quartile => EcLevel::Q,
+            QrCorrection::High => EcLevel::H,
+        }
+    }
+}
+
+/// The modules of a QR code surrounded by a quiet zone of `margin`
+/// modules.
+struct Qr{
+    colors: Vec<Color>,
+    width: u32,
+    margin: u32,
+}
+
+impl Qr{
+    fn new(data: &str, correction: QrCorrection, margin: u32) -> Result<Self, qrcode::types::QrError>{
+        let code = QrCode::with_error_correction_level(data, correction.into())?;
+        Ok(Self{
+            width: code.width() as u32,
+            colors: code.into_colors(),
+            margin,
+        })
+    }
+
+    /// Modules on each side, quiet zone included.
+    fn side(&self) -> u32{
+        self.width + 2 * self.margin
+    }
+
+    fn is_dark(&self, x: u32, y: u32) -> bool{
+        let (x, y) = (x.wrapping_sub(self.margin), y.wrapping_sub(self.margin));
+        x < self.width && y < self.width
+            && self.colors[(y * self.width + x) as usize] == Color::Dark
+    }
+
+    fn to_svg(&self, size: u32) -> String{
+        let side = self.side();
+        let mut path = String::new();
+        for y in 0..side{
+            for x in 0..side{
+                if self.is_dark(x, y){
+                    path.push_str(&format!("M{} {}h1v1h-1z", x, y));
+                }
+            }
+        }
+        format!(concat!(
+            r#"<svg xmlns="http://www.w3.org/2000/svg" version="1.1" width="{size}" height="{size}" "#,
+            r#"viewBox="0 0 {side} {side}" shape-rendering="crispEdges">"#,
+            r##"<rect width="{side}" height="{side}" fill="#fff"/>"##,
+            r##"<path fill="#000" d="{path}"/></svg>"##),
+            size = size, side = side, path = path)
+    }
+
+    /// Draws the code as a grayscale png, scaling every module to the same
+    /// whole number of pixels, so it can be a bit smaller than `size`.
+    fn to_png(&self, size: u32) -> Result<Vec<u8>, png::EncodingError>{
+        let side = self.side();
+        let scale = (size / side).max(1);
+        let dimension = side * scale;
+        let mut pixels = Vec::with_capacity((dimension * dimension) as usize);
+        for y in 0..dimension{
+            for x in 0..dimension{
+                pixels.push(if self.is_dark(x / scale, y / scale) { 0 } else { 255 });
+            }
+        }
+        let mut data = Vec::new();
+        let mut encoder = png::Encoder::new(&mut data, dimension, dimension);
+        encoder.set_color(png::ColorType::Grayscale);
+        encoder.set_depth(png::BitDepth::Eight);
+        encoder.write_header()?.write_image_data(&pixels)?;
+        Ok(data)
+    }
+}
+
+async fn read_qr(ctx: &ApiContext, domain: &LinkDomain, path: &str, params: &QrParameters)
+        -> Result<Qr, Response>{
+    let url = match Url::read_from_short(&ctx.pool, domain.get_id(), path).await{
+        Ok(url) => url,
+        Err(_) => return Err(StatusCode::NOT_FOUND.into_response()),
+    };
+    let margin = params.margin.unwrap_or(DEFAULT_MARGIN).min(MAX_MARGIN);
+    let data = ctx.config.get_short_url(url.get_domain(), &url.get_url());
+    Qr::new(&data, params.ec.unwrap_or_default(), margin).map_err(|e| {
+        error!("Can't make the QR code of {}. {}", data, e);
+        StatusCode::INTERNAL_SERVER_ERROR.into_response()
+    })
+}
+
+fn get_size(params: &QrParameters) -> u32{
+    params.size.unwrap_or(DEFAULT_SIZE).clamp(1, MAX_SIZE)
+}
+
+async fn qr_svg(
+    ctx: Extension<ApiContext>,
+    domain: LinkDomain,
+    Path(path): Path<String>,
+    Query(params): Query<QrParameters>,
+) -> impl IntoResponse{
+    match read_qr(&ctx, &domain, &path, &params).await{
+        Ok(qr) => ([(header::CONTENT_TYPE, "image/svg+xml")], qr.to_svg(get_size(&params)))
+            .into_response(),
+        Err(response) => response,
+    }
+}
+
+async fn qr_png(
+    ctx: Extension<ApiContext>,
+    domain: LinkDomain,
+    Path(path): Path<String>,
+    Query(params): Query<QrParameters>,
+) -> impl IntoResponse{
+    let qr = match read_qr(&ctx, &domain, &path, &params).await{
+        Ok(qr) => qr,
+        Err(response) => return response,
+    };
+    match qr.to_png(get_size(&params)){
+        Ok(data) => ([(header::CONTENT_TYPE, "image/png")], data).into_response(),
+        Err(e) => {
+            error!("Can't encode the QR code of {}. {}", path, e);
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+#[test]
+fn draws_quiet_zone(){
+    let qr = Qr::new("https://localhost/abc", QrCorrection::Medium, 2).unwrap();
+    assert!(qr.side() == qr.width + 4);
+    assert!(!qr.is_dark(0, 0));
+    assert!(!qr.is_dark(1, 1));
+    // Top left corner of the finder pattern
+    assert!(qr.is_dark(2, 2));
+    assert!(qr.to_svg(100).contains("viewBox=\"0 0 29 29\""));
+}
@@ -0,0 +1,317 @@
+use axum::{
+    Router,
+    Extension,
+    routing::{get, post},
+    response::{
+        IntoResponse,
+        Html,
+        Response,
+    },
+    http::StatusCode,
+    extract::{Json, Path, Query},
+};
+use chrono::{DateTime, Duration, TimeZone, Utc};
+use tracing::error;
+use tera::{Tera, Context};
+use serde::Serialize;
+use crate::model::{
+    url::{RedirectType, Url, ShortUrl},
+    click::{Bucket, Click, Count},
+    rule::Rule,
+    variant::Variant,
+    parameters::{FormatParameters, StatsParameters},
+};
+
+use super::{json_error, ApiContext, LinkDomain, RequireManage, RequireStats};
+
+const CHART_WIDTH: f64 = 600.0;
+const CHART_HEIGHT: f64 = 200.0;
+const CHART_LABELS: usize = 6;
+const LATEST_CLICKS: i64 = 50;
+
+
+pub fn router() -> Router{
+    Router::new()
+    .route("/_stats/:short",
+        get(get_link_stats)
+    )
+    .route("/_stats/:short/enable",
+        post(enable)
+    )
+    .route("/_stats/:short/disable",
+        post(disable)
+    )
+    .route("/_campaigns",
+        get(get_campaigns)
+    )
+}
+
+#[derive(Serialize)]
+struct LinkStats{
+    link: ShortUrl,
+    /// The one the link uses, its own or the default.
+    redirect_type: RedirectType,
+    bucket: Bucket,
+    from: DateTime<Utc>,
+    to: DateTime<Utc>,
+    total: i64,
+    series: Vec<Count>,
+    referrers: Vec<Count>,
+    user_agents: Vec<Count>,
+    rules: Vec<RuleClicks>,
+    /// Clicks that no rule matched, sent to the link itself.
+    unmatched: i64,
+    variants: Vec<VariantClicks>,
+    /// The latest clicks of the range.
+    clicks: Vec<Click>,
+}
+
+/// A rule of the link and the clicks it sent to its destination.
+#[derive(Serialize)]
+struct RuleClicks{
+    #[serde(flatten)]
+    rule: Rule,
+    clicks: i64,
+}
+
+/// A variant of the link and the clicks it got.
+#[derive(Serialize)]
+struct VariantClicks{
+    #[serde(flatten)]
+    variant: Variant,
+    clicks: i64,
+}
+
+#[derive(Serialize)]
+struct Bar{
+    x: f64,
+    y: f64,
+    width: f64,
+    height: f64,
+    key: String,
+    clicks: i64,
+}
+
+#[derive(Serialize)]
+struct Label{
+    x: f64,
+    text: String,
+}
+
+/// Geometry of the bar chart, so the template only has to draw it.
+#[derive(Serialize)]
+struct Chart{
+    width: f64,
+    height: f64,
+    max: i64,
+    bars: Vec<Bar>,
+    labels: Vec<Label>,
+}
+
+impl Chart{
+    fn new(series: &[Count]) -> Self{
+        let max = series.iter().map(|count| count.clicks).max().unwrap_or(0);
+        let width = CHART_WIDTH / series.len().max(1) as f64;
+        let bars = series.iter()
+            .enumerate()
+            .map(|(position, count)| {
+                let height = if max > 0 {
+                    CHART_HEIGHT * count.clicks as f64 / max as f64
+                }else{
+                    0.0
+                };
+                Bar{
+                    x: position as f64 * width,
+                    y: CHART_HEIGHT - height,
+                    width,
+                    height,
+                    key: count.key.clone(),
+                    clicks: count.clicks,
+                }
+            })
+            .collect::<Vec<Bar>>();
+        let every = (bars.len() / CHART_LABELS).max(1);
+        let labels = bars.iter()
+            .step_by(every)
+            .map(|bar| Label{ x: bar.x, text: bar.key.clone() })
+            .collect();
+        Self{
+            width: CHART_WIDTH,
+            height: CHART_HEIGHT,
+            max,
+            bars,
+            labels,
+        }
+    }
+}
+
+fn not_found(t: &Tera, short: &str, json: bool) -> Response{
+    if json{
+        return json_error(StatusCode::NOT_FOUND, "Link not found", short);
+    }
+    let mut context = Context::new();
+    context.insert("error", &format!("There is no link '{}'", short));
+    (StatusCode::NOT_FOUND, Html(t.render("error.html", &context).unwrap()))
+        .into_response()
+}
+
+fn bad_request(t: &Tera, message: &str, json: bool) -> Response{
+    if json{
+        return json_error(StatusCode::BAD_REQUEST, message, "");
+    }
+    let mut context = Context::new();
+    context.insert("error", message);
+    (StatusCode::BAD_REQUEST, Html(t.render("error.html", &context).unwrap()))
+        .into_response()
+}
+
+/// The range asked for, from the start of `from` to the end of `to`, or
+/// `None` when any end of it can't be put in a bucket.
+fn range(bucket: Bucket, params: &StatsParameters) -> Option<(DateTime<Utc>, DateTime<Utc>)>{
+    let to = match params.to{
+        Some(to) => Utc.from_utc_datetime(&to.and_hms_opt(0, 0, 0)?)
+            .checked_add_signed(Duration::days(1))?,
+        None => Utc::now(),
+    };
+    let from = match params.from{
+        Some(from) => Utc.from_utc_datetime(&from.and_hms_opt(0, 0, 0)?),
+        None => to.checked_sub_signed(bucket.default_range())?,
+    };
+    bucket.truncate(from)?;
+    bucket.truncate(to)?;
+    Some((from, to))
+}
+
+async fn get_link_stats(
+    _auth: RequireStats,
+    ctx: Extension<ApiContext>,
+    t: Extension<Tera>,
+    domain: LinkDomain,
+    Path(short): Path<String>,
+    Query(params): Query<StatsParameters>,
+) -> impl IntoResponse{
+    let json = params.format.as_deref() == Some("json");
+    let url = match Url::read_from_short(&ctx.pool, domain.get_id(), &short).await{
+        Ok(url) => url,
+        Err(_) => return not_found(&t, &short, json),
+    };
+    let bucket = params.bucket.unwrap_or(Bucket::Day);
+    let (from, to) = match range(bucket, &params){
+        Some(range) => range,
+        None => return bad_request(&t, "The dates are out of range", json),
+    };
+    let id = url.get_id();
+    let result = async {
+        Ok::<_, sqlx::Error>((
+            Click::count_for_url(&ctx.pool, id, from, to).await?,
+            Click::series_for_url(&ctx.pool, id, bucket, from, to).await?,
+            Click::top_referrers(&ctx.pool, id, from, to).await?,
+            Click::top_user_agents(&ctx.pool, id, from, to).await?,
+            Rule::read_for_url(&ctx.pool, id).await?,
+            Click::count_by_rule(&ctx.pool, id, from, to).await?,
+            Variant::read_for_url(&ctx.pool, id).await?,
+            Click::count_by_variant(&ctx.pool, id, from, to).await?,
+            Click::read_for_url(&ctx.pool, id, from, to, LATEST_CLICKS).await?,
+        ))
+    }.await;
+    let (total, series, referrers, user_agents, rules, by_rule, variants, by_variant, latest) = match result{
+        Ok(result) => result,
+        Err(e) => {
+            error!("Can't read stats of {}. {}", short, e);
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+    let clicks = |rule_id: Option<i64>| by_rule.iter()
+        .find(|count| count.rule_id == rule_id)
+        .map_or(0, |count| count.clicks);
+    let rules = rules.into_iter()
+        .map(|rule| RuleClicks{ clicks: clicks(Some(rule.get_id())), rule })
+        .collect();
+    let variants = variants.into_iter()
+        .map(|variant| VariantClicks{
+            clicks: by_variant.iter()
+                .find(|count| count.variant_id == Some(variant.get_id()))
+                .map_or(0, |count| count.clicks),
+            variant,
+        })
+        .collect();
+    let stats = LinkStats{
+        link: url.get_short(),
+        redirect_type: url.get_redirect_type()
+            .unwrap_or_else(|| ctx.config.get_redirect_type()),
+        bucket,
+        from,
+        to,
+        total,
+        series,
+        referrers,
+        user_agents,
+        rules,
+        unmatched: clicks(None),
+        variants,
+        clicks: latest,
+    };
+    if json{
+        return Json(stats).into_response();
+    }
+    let mut context = Context::new();
+    context.insert("chart", &Chart::new(&stats.series));
+    context.insert("stats", &stats);
+    context.insert("from_date", &from.format("%Y-%m-%d").to_string());
+    context.insert("to_date", &(to - Duration::seconds(1)).format("%Y-%m-%d").to_string());
+    Html(t.render("link_stats.html", &context).unwrap()).into_response()
+}
+
+/// The links with a campaign, grouped by it.
+async fn get_campaigns(
+    _auth: RequireStats,
+    ctx: Extension<ApiContext>,
+    t: Extension<Tera>,
+    Query(params): Query<FormatParameters>,
+) -> impl IntoResponse{
+    let campaigns = match Url::read_campaigns(&ctx.pool).await{
+        Ok(campaigns) => campaigns,
+        Err(e) => {
+            error!("Can't read campaigns. {}", e);
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+    if params.format.as_deref() == Some("json"){
+        return Json(campaigns).into_response();
+    }
+    let mut context = Context::new();
+    context.insert("campaigns", &campaigns);
+    Html(t.render("campaigns.html", &context).unwrap()).into_response()
+}
+
+async fn enable(
+    _auth: RequireManage,
+    ctx: Extension<ApiContext>,
+    domain: LinkDomain,
+    Path(short): Path<String>,
+) -> impl IntoResponse{
+    set_active(&ctx, &domain, &short, true).await
+}
+
+async fn disable(
+    _auth: RequireManage,
+    ctx: Extension<ApiContext>,
+    domain: LinkDomain,
+    Path(short): Path<String>,
+) -> impl IntoResponse{
+    set_active(&ctx, &domain, &short, false).await
+}
+
+async fn set_active(ctx: &ApiContext, domain: &LinkDomain, short: &str, active: bool) -> Response{
+    let url = match Url::read_from_short(&ctx.pool, domain.get_id(), short).await{
+        Ok(url) => url,
+        Err(_) => return json_error(StatusCode::NOT_FOUND, "Link not found", short),
+    };
+    match Url::set_active(&ctx.pool, url.get_id(), active).await{
+        Ok(url) => Json(url.get_short()).into_response(),
+        Err(e) => {
+            error!("Can't set active of {}. {}", short, e);
+            json_error(StatusCode::INTERNAL_SERVER_ERROR, &e.to_string(), short)
+        }
+    }
+}
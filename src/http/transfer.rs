@@ -0,0 +1,191 @@
+use axum::{
+    Router,
+    Extension,
+    routing::{get, post},
+    response::IntoResponse,
+    http::{StatusCode, header},
+    body::{boxed, Body, Bytes},
+    extract::{Json, Multipart, Query},
+};
+use futures::TryStreamExt;
+use tracing::error;
+use serde::Serialize;
+use crate::model::{
+    url::{Url, ShortUrl, ImportRow, ImportResult, ImportStatus},
+    parameters::{ExportFormat, ExportParameters},
+};
+
+use super::{ApiContext, RequireManage, json_error, url::check_destination};
+
+
+pub fn router() -> Router{
+    Router::new()
+    .route("/_export",
+        get(export)
+    )
+    .route("/_import",
+        post(import)
+    )
+}
+
+#[derive(Serialize)]
+struct ImportReport{
+    created: usize,
+    duplicate: usize,
+    invalid: usize,
+    rows: Vec<ImportResult>,
+}
+
+impl ImportReport{
+    fn new(rows: Vec<ImportResult>) -> Self{
+        let count = |f: fn(&ImportStatus) -> bool| rows.iter()
+            .filter(|row| f(&row.status))
+            .count();
+        Self{
+            created: count(|status| matches!(status, ImportStatus::Created{..})),
+            duplicate: count(|status| matches!(status, ImportStatus::Duplicate{..})),
+            invalid: count(|status| matches!(status, ImportStatus::Invalid{..})),
+            rows,
+        }
+    }
+}
+
+/// Encodes a url as a chunk of the export, with the csv header in front
+/// of the first one.
+fn encode(format: ExportFormat, url: &ShortUrl, first: bool) -> anyhow::Result<Vec<u8>>{
+    match format{
+        ExportFormat::Csv => {
+            let mut writer = csv::WriterBuilder::new()
+                .has_headers(first)
+                .from_writer(Vec::new());
+            writer.serialize(url)?;
+            Ok(writer.into_inner()?)
+        },
+        ExportFormat::Ndjson => {
+            let mut line = serde_json::to_vec(url)?;
+            line.push(b'\n');
+            Ok(line)
+        },
+    }
+}
+
+/// Needs the manage scope, the same as the import, as the rows carry the
+/// hashes of the passwords.
+async fn export(
+    _auth: RequireManage,
+    ctx: Extension<ApiContext>,
+    Query(params): Query<ExportParameters>,
+) -> impl IntoResponse{
+    let format = params.format.unwrap_or_default();
+    let (mut sender, body) = Body::channel();
+    let pool = ctx.pool.clone();
+    let tag = params.tag.map(|tag| tag.trim().to_lowercase());
+    tokio::spawn(async move {
+        let mut urls = Url::read_all(&pool, tag.as_deref());
+        let mut first = true;
+        loop{
+            let chunk = match urls.try_next().await{
+                Ok(Some(url)) => encode(format, &url.get_export(), std::mem::take(&mut first)),
+                Ok(None) => return,
+                Err(e) => Err(e.into()),
+            };
+            match chunk{
+                Ok(chunk) => if sender.send_data(Bytes::from(chunk)).await.is_err(){
+                    // The client went away
+                    return;
+                },
+                Err(e) => {
+                    error!("Can't export urls. {}", e);
+                    sender.abort();
+                    return;
+                },
+            }
+        }
+    });
+    let (content_type, extension) = match format{
+        ExportFormat::Csv => ("text/csv; charset=utf-8", "csv"),
+        ExportFormat::Ndjson => ("application/x-ndjson", "ndjson"),
+    };
+    ([
+        (header::CONTENT_TYPE, content_type.to_string()),
+        (header::CONTENT_DISPOSITION, format!("attachment; filename=\"links.{}\"", extension)),
+    ], boxed(body))
+}
+
+/// Reads the uploaded file, the field named `file` or the first one with
+/// a file name.
+async fn read_upload(multipart: &mut Multipart) -> Result<Option<String>, String>{
+    while let Some(field) = multipart.next_field().await.map_err(|e| e.to_string())?{
+        if field.name() == Some("file") || field.file_name().is_some(){
+            let bytes = field.bytes().await.map_err(|e| e.to_string())?;
+            return String::from_utf8(bytes.to_vec())
+                .map(Some)
+                .map_err(|_| "The file is not valid UTF-8".to_string());
+        }
+    }
+    Ok(None)
+}
+
+/// Parses a JSON array, NDJSON or CSV with a header row, telling them
+/// apart by the first character. A row that can't be parsed is kept as an
+/// error so it shows up in the report.
+fn parse_rows(content: &str) -> Result<Vec<Result<ImportRow, String>>, String>{
+    let content = content.trim_start_matches('\u{feff}');
+    match content.trim_start().chars().next(){
+        Some('[') => serde_json::from_str::<Vec<serde_json::Value>>(content)
+            .map_err(|e| e.to_string())
+            .map(|values| values.into_iter()
+                .map(|value| serde_json::from_value(value).map_err(|e| e.to_string()))
+                .collect()),
+        Some('{') => Ok(content.lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(|line| serde_json::from_str(line).map_err(|e| e.to_string()))
+            .collect()),
+        _ => Ok(csv::ReaderBuilder::new()
+            .trim(csv::Trim::All)
+            .from_reader(content.as_bytes())
+            .deserialize()
+            .map(|row| row.map_err(|e| e.to_string()))
+            .collect()),
+    }
+}
+
+/// Validates the destinations of a row as if it was created by hand.
+async fn check_row(ctx: &ApiContext, mut row: ImportRow) -> Result<ImportRow, String>{
+    row.src = check_destination(ctx, &row.src).await.map_err(|(_, message)| message)?;
+    if let Some(fallback) = row.fallback_url.take().filter(|fallback| !fallback.is_empty()){
+        row.fallback_url = Some(check_destination(ctx, &fallback).await
+            .map_err(|(_, message)| message)?);
+    }
+    Ok(row)
+}
+
+async fn import(
+    _auth: RequireManage,
+    ctx: Extension<ApiContext>,
+    mut multipart: Multipart,
+) -> impl IntoResponse{
+    let content = match read_upload(&mut multipart).await{
+        Ok(Some(content)) => content,
+        Ok(None) => return json_error(StatusCode::BAD_REQUEST, "There is no file to import", ""),
+        Err(message) => return json_error(StatusCode::BAD_REQUEST, &message, ""),
+    };
+    let rows = match parse_rows(&content){
+        Ok(rows) => rows,
+        Err(message) => return json_error(StatusCode::BAD_REQUEST, &message, ""),
+    };
+    let mut checked = Vec::with_capacity(rows.len());
+    for row in rows{
+        checked.push(match row{
+            Ok(row) => check_row(&ctx, row).await,
+            Err(message) => Err(message),
+        });
+    }
+    match Url::import(&ctx.pool, ctx.config.get_slug_strategy(), checked).await{
+        Ok(results) => Json(ImportReport::new(results)).into_response(),
+        Err(e) => {
+            error!("Can't import urls. {}", e);
+            json_error(StatusCode::INTERNAL_SERVER_ERROR, &e.to_string(), "")
+        }
+    }
+}
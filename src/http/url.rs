@@ -1,4 +1,4 @@
-use std::time::Duration;
+use std::{net::IpAddr, time::Duration};
 
 use axum::{
     Router,
@@ -8,16 +8,34 @@ use axum::{
         IntoResponse,
         Html,
         Redirect,
+        Response,
     },
-    http::header::{self, HeaderValue},
-    extract::{Json, Path},
+    http::{
+        StatusCode,
+        HeaderMap,
+        header::{self, HeaderValue},
+    },
+    extract::{Form, Json, Path, Query, RawQuery},
 };
+use chrono::Utc;
 use tracing::{info, debug, error};
 use tera::{Tera, Context};
 use serde::{Serialize, Deserialize};
-use crate::model::{url::{Url, ShortUrl}, radix::from_d36};
+use crate::{
+    model::{
+        url::{RedirectType, Url, ShortUrl, UrlOptions, Utm},
+        domain::Domain,
+        click::{Click, Visitor},
+        rule::{Agent, Rule},
+        variant::Variant,
+        tag::Tags,
+        parameters::ListParameters,
+    },
+    password,
+    validation::{self, Destination},
+};
 
-use super::ApiContext;
+use super::{ApiContext, ClientIp, HostDomain, RequireCreate, RequireStats, info};
 
 
 pub fn router() -> Router{
@@ -38,13 +56,18 @@ pub fn router() -> Router{
         post(do_ping)
     )
     .route("/:path",
-        get(redirect)
+        get(redirect).post(unlock)
+    )
+    .route("/:path/*rest",
+        get(redirect).post(unlock)
     )
 }
 
 #[derive(Deserialize)]
-struct NewUrl{
-    src: String,
+pub(super) struct NewUrl{
+    pub src: String,
+    #[serde(flatten)]
+    pub options: UrlOptions,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -54,91 +77,560 @@ struct UrlResponse{
     message: String,
 }
 
+/// The short path of a url and, for the ones that forward it, the rest of
+/// the path after it.
+#[derive(Deserialize)]
+struct ShortPath{
+    path: String,
+    #[serde(default)]
+    rest: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct Unlock{
+    password: String,
+}
+
+
+fn url_error(src: &str, status: StatusCode, message: &str) -> Response{
+    (status, Json(UrlResponse{
+        src: src.to_string(),
+        status: status.as_u16(),
+        message: message.to_string(),
+    })).into_response()
+}
+
+/// Checks a destination given by a client against the domain policy, and
+/// then validates it. The policy goes first, to not even resolve a domain
+/// that is refused.
+async fn accept(ctx: &ApiContext, src: &str) -> Result<Destination, (StatusCode, String)>{
+    let url = validation::parse(src)
+        .map_err(|e| (StatusCode::BAD_REQUEST, e.to_string()))?;
+    if let Some(host) = url.host_str().filter(|host| !ctx.policy.read().unwrap().allows(host)){
+        return Err((StatusCode::FORBIDDEN, format!("The domain '{}' is not allowed", host)));
+    }
+    validation::validate(src)
+        .await
+        .map_err(|e| (StatusCode::BAD_REQUEST, e.to_string()))
+}
+
+/// Accepts a destination given by a client, returning it normalized.
+pub(super) async fn check_destination(ctx: &ApiContext, src: &str) -> Result<String, (StatusCode, String)>{
+    accept(ctx, src)
+        .await
+        .map(|destination| destination.url.to_string())
+}
+
+/// Trims a free text given by a client, leaving it out when it is empty.
+pub(super) fn clean_text(text: Option<String>) -> Option<String>{
+    text.map(|text| text.trim().to_string())
+        .filter(|text| !text.is_empty())
+}
+
+/// Creates a url, or returns the existing one when `src` was already
+/// shortened the same way. It goes to the domain of `options` or else to
+/// the one of the request.
+pub(super) async fn create_url(ctx: &ApiContext, domain_id: Option<i64>, src: &str,
+        mut options: UrlOptions) -> Result<Url, (StatusCode, String)>{
+    options.domain_id = match options.domain.take().filter(|host| !host.is_empty()){
+        Some(host) => {
+            let normalized = Domain::normalize(&host).unwrap_or_default();
+            match Domain::read_from_host(&ctx.pool, &normalized).await{
+                Ok(domain) => Some(domain.get_id()),
+                Err(sqlx::Error::RowNotFound) => return Err((StatusCode::BAD_REQUEST,
+                    format!("The domain '{}' is not served", host))),
+                Err(e) => {
+                    error!("Can't read domain {}. {}", host, e);
+                    return Err((StatusCode::INTERNAL_SERVER_ERROR, e.to_string()));
+                }
+            }
+        },
+        None => domain_id,
+    };
+    options.strategy = ctx.config.get_slug_strategy().clone();
+    options.slug = options.slug.filter(|slug| !slug.is_empty());
+    options.fallback_url = options.fallback_url.filter(|fallback| !fallback.is_empty());
+    options.password = options.password.filter(|password| !password.is_empty());
+    options.title = clean_text(options.title);
+    options.notes = clean_text(options.notes);
+    options.utm = options.utm.clean();
+    let src = if options.utm_on_redirect{
+        src.to_string()
+    }else{
+        options.utm.tag(src.trim())
+    };
+    let src = &check_destination(ctx, &src).await?;
+    if let Some(fallback) = options.fallback_url.take(){
+        options.fallback_url = Some(check_destination(ctx, &fallback).await?);
+    }
+    // Once in the destination, the UTM parameters are just a label for the
+    // url, and a url is only shortened once anyway
+    let plain = if options.utm_on_redirect{
+        options.clone()
+    }else{
+        UrlOptions{utm: Utm::default(), ..options.clone()}
+    };
+    if plain.is_empty(){
+        return Url::read_or_create(&ctx.pool, src, &options).await.map_err(|e| {
+            error!("Can't create {}. {}", src, e);
+            (StatusCode::INTERNAL_SERVER_ERROR, e.to_string())
+        });
+    }
+    if let Some(slug) = options.slug.as_deref().filter(|slug| !Url::is_valid_slug(slug)){
+        return Err((StatusCode::BAD_REQUEST, format!("The slug '{}' is not valid", slug)));
+    }
+    if matches!(options.expires_at, Some(expires_at) if expires_at <= Utc::now()){
+        return Err((StatusCode::BAD_REQUEST,
+            "The expiration date has already passed".to_string()));
+    }
+    // A url is shortened only once, but a custom slug can still be given to
+    // one that has none.
+    let only_slug = UrlOptions{slug: None, ..plain}.is_empty();
+    let existing = if options.utm_on_redirect{
+        Url::read_from_tagged_url(&ctx.pool, options.domain_id, src, &options.utm).await.ok()
+    }else{
+        Url::read_from_url(&ctx.pool, options.domain_id, src).await.ok()
+    };
+    if let Some(url) = existing.clone(){
+        match (url.get_slug(), options.slug.as_deref()){
+            (Some(current), Some(slug)) if current == slug && only_slug => {
+                return Ok(url);
+            },
+            (None, Some(_)) if only_slug => {},
+            _ => {
+                return Err((StatusCode::CONFLICT,
+                    format!("This url is already shortened as '{}'", url.get_url())));
+            },
+        }
+    }
+    if let Some(slug) = &options.slug{
+        if Url::is_short_taken(&ctx.pool, options.domain_id, slug).await{
+            return Err((StatusCode::CONFLICT,
+                format!("The slug '{}' is already in use", slug)));
+        }
+    }
+    if let Some(password) = options.password.take(){
+        options.password = Some(hash_password(password).await?);
+    }
+    let result = match (existing, &options.slug){
+        (Some(url), Some(slug)) => Url::set_slug(&ctx.pool, url.get_id(), slug).await,
+        _ => Url::create(&ctx.pool, src, &options).await,
+    };
+    result.map_err(|e| {
+        error!("Can't create {}. {}", src, e);
+        (StatusCode::INTERNAL_SERVER_ERROR, e.to_string())
+    })
+}
+
+/// Hashes the password of a url, away from the async workers as argon2 is
+/// slow by design.
+pub(super) async fn hash_password(password: String) -> Result<String, (StatusCode, String)>{
+    tokio::task::spawn_blocking(move || password::hash(&password))
+        .await
+        .map_err(|e| e.to_string())
+        .and_then(|hash| hash.map_err(|e| e.to_string()))
+        .map_err(|e| {
+            error!("Can't hash password. {}", e);
+            (StatusCode::INTERNAL_SERVER_ERROR, e)
+        })
+}
 
 async fn post_shorturl(
+    _auth: RequireCreate,
     ctx: Extension<ApiContext>,
+    HostDomain(domain): HostDomain,
     Json(payload): Json<NewUrl>
 ) -> impl IntoResponse{
-    let src = payload.src;
-    let url = Url::read_or_create(&ctx.pool, &src).await.unwrap();
-    Json(url.get_short())
+    let domain_id = domain.as_ref().map(Domain::get_id);
+    match create_url(&ctx, domain_id, &payload.src, payload.options).await{
+        Ok(url) => Json(url.get_short()).into_response(),
+        Err((status, message)) => url_error(&payload.src, status, &message),
+    }
 }
 
+/// The form to shorten urls, unless the domain sends its root somewhere
+/// else.
 async fn get_shorturl(
-    t: Extension<Tera>
+    auth: Result<RequireCreate, Response>,
+    t: Extension<Tera>,
+    HostDomain(domain): HostDomain,
 ) -> impl IntoResponse{
+    if let Some(root) = domain.as_ref().and_then(Domain::get_root_url){
+        return Redirect::to(root).into_response();
+    }
+    if let Err(response) = auth{
+        return response;
+    }
     let context = Context::new();
     Html(t.render("index.html", &context).unwrap()).into_response()
 }
 
+const MAX_REDIRECTS: usize = 5;
+
+/// How long a visitor is kept on the same variant of a sticky url.
+const VARIANT_COOKIE_AGE: i64 = 30 * 24 * 60 * 60;
+
+/// Sortable columns of the stats table, as (sort key, header).
+const STATS_COLUMNS: [(&str, &str); 4] = [
+    ("src", "Origin"),
+    ("slug", "Short"),
+    ("clicks", "Views"),
+    ("created", "Created"),
+];
+
 async fn get_stats(
+    _auth: RequireStats,
     ctx: Extension<ApiContext>,
-    t: Extension<Tera>
+    t: Extension<Tera>,
+    Query(params): Query<ListParameters>,
 ) -> impl IntoResponse{
     let mut context = Context::new();
-    let urls: Vec<ShortUrl> =  Url::read_all(&ctx.pool).await
+    let search = params.get_search();
+    let tags = Tags::read_all(&ctx.pool).await.unwrap();
+    let per_page = ctx.config.get_page().max(1);
+    let total = Url::count(&ctx.pool, &search).await.unwrap();
+    let pages = ((total + per_page - 1) / per_page).max(1);
+    let page = params.get_page().min(pages);
+    let urls: Vec<ShortUrl> =  Url::search(&ctx.pool, &search, per_page,
+            (page - 1) * per_page).await
         .unwrap()
         .iter()
         .map(|item| item.get_short())
         .collect();
     context.insert("urls", &urls);
+    context.insert("q", &search.query.unwrap_or_default());
+    context.insert("tag", &search.tag.unwrap_or_default());
+    context.insert("tags", &tags);
+    context.insert("sort", &search.sort);
+    context.insert("order", &search.order);
+    context.insert("page", &page);
+    context.insert("pages", &pages);
+    context.insert("total", &total);
+    context.insert("columns", &STATS_COLUMNS);
     Html(t.render("stats.html", &context).unwrap()).into_response()
 }
 
+/// Requests the destination pinned to the addresses it was validated with,
+/// so its name can't resolve somewhere else in between.
+async fn ping(destination: &Destination) -> reqwest::Result<reqwest::Response>{
+    let mut builder = reqwest::Client::builder()
+        .timeout(Duration::from_secs(5))
+        .redirect(reqwest::redirect::Policy::none());
+    if let Some(domain) = destination.url.domain(){
+        builder = builder.resolve_to_addrs(domain, &destination.addrs);
+    }
+    builder.build()?
+        .get(destination.url.clone())
+        .send()
+        .await
+}
+
+/// Checks that a url answers, following the redirects by hand so every
+/// hop is validated as the first one.
 async fn do_ping(
+    _auth: RequireCreate,
+    ctx: Extension<ApiContext>,
     Json(payload): Json<NewUrl>
 ) -> impl IntoResponse{
     let src = payload.src;
     info!("Do ping from {}", &src);
-    let client = reqwest::Client::builder()
-        .timeout(Duration::from_secs(5))
-        .build()
-        .unwrap();
-    match client.get(&src)
-        .send()
-        .await{
-            Ok(response) => {
-                debug!("Response: {:?}", response);
-                Json(UrlResponse{
-                    src: src.clone(),
-                    status: response.status().as_u16(),
-                    message: "No se".to_string(),
-                })
-            },
+    let mut next = src.clone();
+    for _ in 0..=MAX_REDIRECTS{
+        let destination = match accept(&ctx, &next).await{
+            Ok(destination) => destination,
+            Err((status, message)) => return url_error(&src, status, &message),
+        };
+        let response = match ping(&destination).await{
+            Ok(response) => response,
             Err(e) => {
                 debug!("Error: {:?}", e);
-                Json(UrlResponse{
-                    src: src.clone(),
+                return Json(UrlResponse{
+                    src,
                     status: 500,
                     message: e.to_string(),
-                })
+                }).into_response();
             }
+        };
+        debug!("Response: {:?}", response);
+        let status = response.status();
+        let location = response.headers()
+            .get(header::LOCATION)
+            .and_then(|location| location.to_str().ok())
+            .and_then(|location| destination.url.join(location).ok());
+        match location{
+            Some(location) if status.is_redirection() => next = location.to_string(),
+            _ => return Json(UrlResponse{
+                src,
+                status: status.as_u16(),
+                message: status.canonical_reason().unwrap_or_default().to_string(),
+            }).into_response(),
         }
+    }
+    url_error(&src, StatusCode::BAD_REQUEST, "The url redirects too many times")
 }
+fn header_value(headers: &HeaderMap, name: header::HeaderName) -> Option<String>{
+    headers.get(name)
+        .and_then(|value| value.to_str().ok())
+        .map(|value| value.to_string())
+}
+
+/// The response for a url that can't be visited, if it is the case.
+fn unavailable(ctx: &ApiContext, t: &Tera, path: &str, url: &Url) -> Option<Response>{
+    let mut context = Context::new();
+    context.insert("short", path);
+    let policy = ctx.policy.read().unwrap();
+    if !policy.allows_url(url.get_src()){
+        debug!("Blocked: {}", path);
+        return Some((StatusCode::FORBIDDEN,
+            Html(t.render("blocked.html", &context).unwrap())).into_response());
+    }
+    if url.is_expired(){
+        debug!("Expired: {}", path);
+        return Some(match url.get_fallback_url().filter(|fallback| policy.allows_url(fallback)){
+            Some(fallback) => leave(t, url.get_redirect_type()
+                .unwrap_or_else(|| ctx.config.get_redirect_type()), fallback),
+            None => (StatusCode::GONE, Html(t.render("gone.html", &context).unwrap()))
+                .into_response(),
+        });
+    }
+    if !url.is_active(){
+        debug!("Disabled: {}", path);
+        return Some((StatusCode::NOT_FOUND,
+            Html(t.render("disabled.html", &context).unwrap())).into_response());
+    }
+    None
+}
+
+/// The url behind a short path of a domain. A longer path only leads to
+/// the urls that forward it.
+async fn read_url(ctx: &ApiContext, domain: Option<&Domain>, path: &str, rest: Option<&str>)
+        -> Result<Url, sqlx::Error>{
+    let url = Url::read_from_short(&ctx.pool, domain.map(Domain::get_id), path).await?;
+    if rest.is_some() && !url.is_forwarding_path(){
+        return Err(sqlx::Error::RowNotFound);
+    }
+    Ok(url)
+}
+
+/// The page for a path that leads to no url, or the one the domain sends
+/// them to. Failing to read the url gives the error page instead.
+fn not_found(t: &Tera, domain: Option<&Domain>, path: &str, e: sqlx::Error) -> Response{
+    let mut context = Context::new();
+    if !matches!(e, sqlx::Error::RowNotFound){
+        error!("Can't read {}. {}", path, e);
+        context.insert("error", &e.to_string());
+        return (StatusCode::INTERNAL_SERVER_ERROR, Html(t.render("error.html", &context).unwrap()))
+            .into_response();
+    }
+    if let Some(page) = domain.and_then(Domain::get_not_found_url){
+        return Redirect::to(page).into_response();
+    }
+    context.insert("short", path);
+    (StatusCode::NOT_FOUND, Html(t.render("not_found.html", &context).unwrap())).into_response()
+}
+
+fn password_prompt(t: &Tera, path: &str, error: Option<&str>, status: StatusCode) -> Response{
+    let mut context = Context::new();
+    context.insert("short", path);
+    context.insert("error", &error);
+    (status, Html(t.render("password.html", &context).unwrap())).into_response()
+}
+
+/// Sends the visitor to `src` the way `redirect_type` says.
+fn leave(t: &Tera, redirect_type: RedirectType, src: &str) -> Response{
+    let status = match redirect_type{
+        RedirectType::MovedPermanently => StatusCode::MOVED_PERMANENTLY,
+        RedirectType::Found => StatusCode::FOUND,
+        RedirectType::TemporaryRedirect => StatusCode::TEMPORARY_REDIRECT,
+        RedirectType::PermanentRedirect => StatusCode::PERMANENT_REDIRECT,
+        RedirectType::Refresh | RedirectType::Interstitial => {
+            let mut context = Context::new();
+            context.insert("src", src);
+            context.insert("interstitial", &(redirect_type == RedirectType::Interstitial));
+            return Html(t.render("leaving.html", &context).unwrap()).into_response();
+        },
+    };
+    match HeaderValue::try_from(src){
+        Ok(location) => (status, [(header::LOCATION, location)]).into_response(),
+        Err(e) => {
+            error!("Can't redirect to {}. {}", src, e);
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+/// The first rule of the url that matches the visitor and leads to a
+/// domain that is still allowed.
+async fn find_rule(ctx: &ApiContext, url: &Url, user_agent: Option<&str>) -> Option<Rule>{
+    let rules = match Rule::read_for_url(&ctx.pool, url.get_id()).await{
+        Ok(rules) => rules,
+        Err(e) => {
+            error!("Can't read rules of {}. {}", url.get_src(), e);
+            return None;
+        }
+    };
+    if rules.is_empty(){
+        return None;
+    }
+    let agent = Agent::parse(user_agent.unwrap_or_default());
+    let policy = ctx.policy.read().unwrap();
+    rules.into_iter().find(|rule| rule.matches(&agent) && policy.allows_url(rule.get_src()))
+}
+
+fn variant_cookie(url: &Url) -> String{
+    format!("shortrs_variant_{}", url.get_id())
+}
+
+/// The id of the variant a sticky url gave the visitor before, if any.
+fn sticky_variant(headers: &HeaderMap, url: &Url) -> Option<i64>{
+    let name = variant_cookie(url);
+    headers.get_all(header::COOKIE)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(';'))
+        .filter_map(|cookie| cookie.trim().split_once('='))
+        .find(|(key, _)| *key == name)
+        .and_then(|(_, value)| value.parse().ok())
+}
+
+/// The variant of the url the visit goes to, among the ones that lead to a
+/// domain that is still allowed, and whether the visitor got it just now.
+async fn find_variant(ctx: &ApiContext, headers: &HeaderMap, url: &Url) -> Option<(Variant, bool)>{
+    let variants = match Variant::read_for_url(&ctx.pool, url.get_id()).await{
+        Ok(variants) => variants,
+        Err(e) => {
+            error!("Can't read variants of {}. {}", url.get_src(), e);
+            return None;
+        }
+    };
+    if variants.is_empty(){
+        return None;
+    }
+    let variants: Vec<Variant> = {
+        let policy = ctx.policy.read().unwrap();
+        variants.into_iter().filter(|variant| policy.allows_url(variant.get_src())).collect()
+    };
+    let kept = sticky_variant(headers, url)
+        .filter(|_| url.is_sticky_variant())
+        .and_then(|id| variants.iter().find(|variant| variant.get_id() == id));
+    match kept{
+        Some(variant) => Some((variant.clone(), false)),
+        None => Variant::choose(&variants).map(|variant| (variant.clone(), true)),
+    }
+}
+
+/// Counts the visit and sends the client to the destination, to the one of
+/// the first rule that matches it or to one of the variants.
+async fn visit(ctx: &ApiContext, t: &Tera, ip: IpAddr, headers: &HeaderMap, url: &Url,
+        rest: Option<&str>, query: Option<&str>) -> Response{
+    let origin = url.get_src();
+    match Url::increase(&ctx.pool, url).await{
+        Ok(urli) => info!("Visits to {}: {}", origin, urli.get_num()),
+        Err(e) => error!("Can't increase {}. {}", origin, e),
+    };
+    let user_agent = header_value(headers, header::USER_AGENT);
+    let rule = find_rule(ctx, url, user_agent.as_deref()).await;
+    let variant = match rule{
+        Some(_) => None,
+        None => find_variant(ctx, headers, url).await,
+    };
+    let visitor = Visitor{
+        referrer: header_value(headers, header::REFERER),
+        accept_language: header_value(headers, header::ACCEPT_LANGUAGE),
+        client_hash: Visitor::client_hash(&ctx.hash_salt, &ip,
+            user_agent.as_deref()),
+        user_agent,
+    };
+    if let Err(e) = Click::create(&ctx.pool, url.get_id(), rule.as_ref().map(Rule::get_id),
+            variant.as_ref().map(|(variant, _)| variant.get_id()), &visitor).await{
+        error!("Can't record click on {}. {}", origin, e);
+    }
+    let chosen = rule.as_ref().map(Rule::get_src)
+        .or_else(|| variant.as_ref().map(|(variant, _)| variant.get_src()));
+    let destination = url.get_destination(chosen, rest, query);
+    debug!("Destination: {}", destination);
+    let redirect_type = url.get_redirect_type()
+        .unwrap_or_else(|| ctx.config.get_redirect_type());
+    let mut response = leave(t, redirect_type, &destination);
+    if let Some((variant, true)) = variant.filter(|_| url.is_sticky_variant()){
+        let cookie = format!("{}={}; Path=/; Max-Age={}; HttpOnly; SameSite=Lax",
+            variant_cookie(url), variant.get_id(), VARIANT_COOKIE_AGE);
+        if let Ok(cookie) = HeaderValue::try_from(cookie){
+            response.headers_mut().append(header::SET_COOKIE, cookie);
+        }
+    }
+    response
+}
+
 async fn redirect(
     ctx: Extension<ApiContext>,
     t: Extension<Tera>,
-    Path(path): Path<String>,
+    ClientIp(ip): ClientIp,
+    HostDomain(domain): HostDomain,
+    headers: HeaderMap,
+    Path(ShortPath{path, rest}): Path<ShortPath>,
+    RawQuery(query): RawQuery,
 ) -> impl IntoResponse {
-    let id = from_d36(&path).try_into().unwrap();
-    debug!("Index: {}", id);
-    match Url::read(&ctx.pool, id).await{
-        Ok(url) => {
-            let origin = url.get_src();
-            match Url::increase(&ctx.pool, &url).await{
-                Ok(urli) => info!("Visits to {}: {}", origin, urli.get_num()),
-                Err(e) => error!("Can't increase {}. {}", origin, e),
-            };
-            let src = url.get_src();
-            debug!("Src: {}", src);
-            Redirect::to(src).into_response()
-        },
-        Err(e) => {
-            let mut context = Context::new();
-            context.insert("error", &e.to_string());
-            Html(t.render("error.html", &context).unwrap()).into_response()
-        }
+    debug!("Path: {}", path);
+    if let (Some(short), None) = (path.strip_suffix('+'), &rest){
+        return info::preview(&ctx, &t, domain.as_ref().map(Domain::get_id), short, false).await;
+    }
+    let url = match read_url(&ctx, domain.as_ref(), &path, rest.as_deref()).await{
+        Ok(url) => url,
+        Err(e) => return not_found(&t, domain.as_ref(), &path, e),
+    };
+    if let Some(response) = unavailable(&ctx, &t, &path, &url){
+        return response;
+    }
+    if url.is_protected(){
+        debug!("Protected: {}", path);
+        return password_prompt(&t, &path, None, StatusCode::OK);
+    }
+    visit(&ctx, &t, ip, &headers, &url, rest.as_deref(), query.as_deref()).await
+}
+
+/// Checks the password of a protected url, and only then counts the visit
+/// and redirects. A client that fails too many times has to wait.
+#[allow(clippy::too_many_arguments)]
+async fn unlock(
+    ctx: Extension<ApiContext>,
+    t: Extension<Tera>,
+    ClientIp(ip): ClientIp,
+    HostDomain(domain): HostDomain,
+    headers: HeaderMap,
+    Path(ShortPath{path, rest}): Path<ShortPath>,
+    RawQuery(query): RawQuery,
+    Form(form): Form<Unlock>,
+) -> impl IntoResponse {
+    let url = match read_url(&ctx, domain.as_ref(), &path, rest.as_deref()).await{
+        Ok(url) => url,
+        Err(e) => return not_found(&t, domain.as_ref(), &path, e),
+    };
+    if let Some(response) = unavailable(&ctx, &t, &path, &url){
+        return response;
+    }
+    let hash = match url.get_password(){
+        Some(hash) => hash.to_string(),
+        None => return visit(&ctx, &t, ip, &headers, &url, rest.as_deref(), query.as_deref()).await,
+    };
+    let key = (ip, url.get_id());
+    if let Some(wait) = ctx.attempts.retry_after(&key){
+        let mut response = password_prompt(&t, &path,
+            Some("Too many attempts, try again later"), StatusCode::TOO_MANY_REQUESTS);
+        response.headers_mut().insert(header::RETRY_AFTER,
+            HeaderValue::from(wait.as_secs().max(1)));
+        return response;
+    }
+    let valid = tokio::task::spawn_blocking(move || password::verify(&form.password, &hash))
+        .await
+        .unwrap_or(false);
+    if !valid{
+        debug!("Wrong password for {}", path);
+        ctx.attempts.fail(key);
+        return password_prompt(&t, &path, Some("Wrong password"), StatusCode::FORBIDDEN);
     }
+    ctx.attempts.clear(&key);
+    visit(&ctx, &t, ip, &headers, &url, rest.as_deref(), query.as_deref()).await
 }
 
 
@@ -1,17 +1,31 @@
 use config::Configuration;
 use tracing_subscriber::EnvFilter;
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
-use sqlx::{sqlite::SqlitePoolOptions, migrate::{Migrator, MigrateDatabase}};
-use std::{env, path::Path};
+use sqlx::{sqlite::{SqlitePool, SqlitePoolOptions}, migrate::{Migrator, MigrateDatabase}};
+use std::{env, path::Path, sync::{Arc, RwLock}, time::Duration};
 use std::str::FromStr;
-use tracing::info;
+use tracing::{info, error};
+use model::url::Url;
+use policy::DomainPolicy;
+
+/// Seconds between the checks for expired urls
+const EXPIRATION_INTERVAL: u64 = 60;
 
 mod model;
 mod config;
 mod http;
+mod password;
+mod validation;
+mod policy;
 
 #[tokio::main]
 async fn main(){
+    // `shortrs hash <password>` prints the value for `password` in config.yml
+    let args: Vec<String> = env::args().collect();
+    if args.len() == 3 && args[1] == "hash"{
+        println!("{}", password::hash(&args[2]).unwrap());
+        return;
+    }
     let configuration = Configuration::read().await;
 
     tracing_subscriber::registry()
@@ -42,5 +56,32 @@ async fn main(){
         .run(&pool)
         .await
         .unwrap();
-    http::serve(configuration, pool).await.unwrap();
+    tokio::spawn(deactivate_expired(pool.clone()));
+    let policy = match configuration.get_domain_policy(){
+        Some(path) => match DomainPolicy::read(path).await{
+            Ok(policy) => policy,
+            Err(e) => {
+                println!("Error with domain policy `{}`: {}", path, e);
+                std::process::exit(0);
+            }
+        },
+        None => DomainPolicy::default(),
+    };
+    let policy = Arc::new(RwLock::new(policy));
+    if let Some(path) = configuration.get_domain_policy(){
+        tokio::spawn(policy::watch(path.to_string(), policy.clone()));
+    }
+    http::serve(configuration, pool, policy).await.unwrap();
+}
+
+async fn deactivate_expired(pool: SqlitePool){
+    let mut interval = tokio::time::interval(Duration::from_secs(EXPIRATION_INTERVAL));
+    loop{
+        interval.tick().await;
+        match Url::deactivate_expired(&pool).await{
+            Ok(0) => {},
+            Ok(value) => info!("Deactivated {} expired urls", value),
+            Err(e) => error!("Can't deactivate expired urls. {}", e),
+        }
+    }
 }
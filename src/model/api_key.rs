@@ -0,0 +1,231 @@
+use serde::{Serialize, Deserialize};
+use sqlx::{sqlite::{SqlitePool, SqliteRow}, query, Row};
+use chrono::{DateTime, Utc};
+use rand::{distributions::Alphanumeric, Rng};
+use sha2::{Digest, Sha256};
+use tracing::{debug, info};
+
+const KEY_PREFIX: &str = "srs_";
+const KEY_LENGTH: usize = 32;
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Scope{
+    Create,
+    ReadStats,
+    Manage,
+}
+
+impl Scope{
+    fn as_str(&self) -> &'static str{
+        match self{
+            Scope::Create => "create",
+            Scope::ReadStats => "read-stats",
+            Scope::Manage => "manage",
+        }
+    }
+    fn parse(value: &str) -> Option<Self>{
+        match value{
+            "create" => Some(Scope::Create),
+            "read-stats" => Some(Scope::ReadStats),
+            "manage" => Some(Scope::Manage),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ApiKey{
+    id: i64,
+    name: String,
+    prefix: String,
+    scopes: Vec<Scope>,
+    active: bool,
+    last_used_at: Option<DateTime<Utc>>,
+    created_at: DateTime<Utc>,
+    updated_at: DateTime<Utc>,
+}
+
+/// The only time the plain key is available is right after its creation.
+#[derive(Serialize)]
+pub struct NewApiKey{
+    key: String,
+    #[serde(flatten)]
+    api_key: ApiKey,
+}
+
+impl ApiKey{
+    pub fn get_name(&self) -> &str{
+        &self.name
+    }
+    pub fn has_scope(&self, scope: Scope) -> bool{
+        self.active && self.scopes.contains(&scope)
+    }
+
+    fn hash(key: &str) -> String{
+        format!("{:x}", Sha256::digest(key.as_bytes()))
+    }
+
+    fn from_row(row: SqliteRow) -> Self{
+        let scopes: String = row.get("scopes");
+        Self{
+            id: row.get("id"),
+            name: row.get("name"),
+            prefix: row.get("prefix"),
+            scopes: scopes.split(',').filter_map(Scope::parse).collect(),
+            active: row.get("active"),
+            last_used_at: row.get("last_used_at"),
+            created_at: row.get("created_at"),
+            updated_at: row.get("updated_at"),
+        }
+    }
+
+    pub async fn create(pool: &SqlitePool, name: &str, scopes: &[Scope]) -> Result<NewApiKey, sqlx::Error>{
+        info!("ApiKey create");
+        let random: String = rand::thread_rng()
+            .sample_iter(&Alphanumeric)
+            .take(KEY_LENGTH)
+            .map(char::from)
+            .collect();
+        let key = format!("{}{}", KEY_PREFIX, random);
+        let prefix = &key[..KEY_PREFIX.len() + 4];
+        let scopes = scopes.iter()
+            .map(|scope| scope.as_str())
+            .collect::<Vec<&str>>()
+            .join(",");
+        let created_at = Utc::now();
+        let sql = "INSERT INTO api_keys (name, prefix, hash, scopes, active,
+                   created_at, updated_at)
+                   VALUES($1, $2, $3, $4, $5, $6, $6) RETURNING *";
+        debug!("Query: {}", sql);
+        let api_key = query(sql)
+            .bind(name)
+            .bind(prefix)
+            .bind(Self::hash(&key))
+            .bind(scopes)
+            .bind(true)
+            .bind(created_at)
+            .map(Self::from_row)
+            .fetch_one(pool)
+            .await?;
+        Ok(NewApiKey{ key, api_key })
+    }
+
+    /// Finds the active key and records its use in the same query.
+    pub async fn authenticate(pool: &SqlitePool, key: &str) -> Result<Self, sqlx::Error>{
+        info!("ApiKey authenticate");
+        let sql = "UPDATE api_keys SET last_used_at = $2
+                   WHERE hash = $1 AND active = TRUE RETURNING *";
+        debug!("Query: {}", sql);
+        query(sql)
+            .bind(Self::hash(key))
+            .bind(Utc::now())
+            .map(Self::from_row)
+            .fetch_one(pool)
+            .await
+    }
+
+    pub async fn read_all(pool: &SqlitePool) -> Result<Vec<Self>, sqlx::Error>{
+        info!("ApiKey read_all");
+        let sql = "SELECT * FROM api_keys ORDER BY id";
+        debug!("Query: {}", sql);
+        query(sql)
+            .map(Self::from_row)
+            .fetch_all(pool)
+            .await
+    }
+
+    pub async fn revoke(pool: &SqlitePool, id: i64) -> Result<Self, sqlx::Error>{
+        info!("ApiKey revoke");
+        let sql = "UPDATE api_keys SET active = FALSE, updated_at = $2
+                   WHERE id = $1 RETURNING *";
+        debug!("Query: {}", sql);
+        query(sql)
+            .bind(id)
+            .bind(Utc::now())
+            .map(Self::from_row)
+            .fetch_one(pool)
+            .await
+    }
+}
+
+#[cfg(test)]
+mod api_key_test {
+    use sqlx::{
+        self,
+        Pool,
+        sqlite::{
+            Sqlite,
+            SqlitePoolOptions,
+        },
+        migrate::{
+            Migrator,
+            MigrateDatabase
+        }
+    };
+    use std::{env, path::Path};
+    use super::{ApiKey, Scope};
+
+    async fn setup(db: &str) -> Pool<Sqlite>{
+        let db_url = format!("sqlite:{}", db);
+        teardown(db).await;
+        if !sqlx::Sqlite::database_exists(&db_url).await.unwrap(){
+            sqlx::Sqlite::create_database(&db_url).await.unwrap();
+        }
+        let crate_dir = env::var("CARGO_MANIFEST_DIR").unwrap();
+        let migrations = Path::new(&crate_dir).join("./migrations");
+        let pool = SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect(&db_url)
+            .await
+            .expect("Pool failed");
+
+        Migrator::new(migrations)
+            .await
+            .unwrap()
+            .run(&pool)
+            .await
+        .unwrap();
+        pool
+    }
+
+    #[allow(unused_must_use)]
+    async fn teardown(db: &str) {
+        tokio::fs::remove_file(db).await;
+        tokio::fs::remove_file(format!("{}-shm", db)).await;
+        tokio::fs::remove_file(format!("{}-wal", db)).await;
+    }
+
+    #[tokio::test]
+    async fn test_authenticate(){
+        let db = "test-api-key-authenticate.db";
+        // Start and prepare
+        let pool = setup(db).await;
+        // Test
+        let new_key = ApiKey::create(&pool, "ci", &[Scope::Create]).await.unwrap();
+        assert!(new_key.api_key.last_used_at.is_none());
+        let api_key = ApiKey::authenticate(&pool, &new_key.key).await.unwrap();
+        assert!(api_key.get_name() == "ci");
+        assert!(api_key.has_scope(Scope::Create));
+        assert!(!api_key.has_scope(Scope::Manage));
+        assert!(api_key.last_used_at.is_some());
+        assert!(ApiKey::authenticate(&pool, "srs_nope").await.is_err());
+        // End and Clean
+        teardown(db).await;
+    }
+
+    #[tokio::test]
+    async fn test_revoke(){
+        let db = "test-api-key-revoke.db";
+        // Start and prepare
+        let pool = setup(db).await;
+        // Test
+        let new_key = ApiKey::create(&pool, "bot", &[Scope::ReadStats]).await.unwrap();
+        let revoked = ApiKey::revoke(&pool, new_key.api_key.id).await.unwrap();
+        assert!(!revoked.has_scope(Scope::ReadStats));
+        assert!(ApiKey::authenticate(&pool, &new_key.key).await.is_err());
+        assert!(ApiKey::read_all(&pool).await.unwrap().len() == 1);
+        // End and Clean
+        teardown(db).await;
+    }
+}
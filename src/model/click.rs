@@ -0,0 +1,480 @@
+use std::{fmt, net::IpAddr, str::FromStr};
+
+use serde::{Serialize, Deserialize};
+use sqlx::{sqlite::{SqlitePool, SqliteRow}, query, Row};
+use chrono::{DateTime, Datelike, Duration, DurationRound, Utc};
+use sha2::{Digest, Sha256};
+use tracing::{debug, info};
+
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Click{
+    id: i64,
+    url_id: i64,
+    referrer: Option<String>,
+    user_agent: Option<String>,
+    accept_language: Option<String>,
+    client_hash: String,
+    rule_id: Option<i64>,
+    variant_id: Option<i64>,
+    created_at: DateTime<Utc>,
+}
+
+/// What a request tells about the visitor, as it arrives to `redirect`.
+#[derive(Debug, Default)]
+pub struct Visitor{
+    pub referrer: Option<String>,
+    pub user_agent: Option<String>,
+    pub accept_language: Option<String>,
+    pub client_hash: String,
+}
+
+impl Visitor{
+    /// Identifies a client without storing its address. The day is part of
+    /// the hash, so the same client can't be followed from one day to the
+    /// next.
+    pub fn client_hash(salt: &str, ip: &IpAddr, user_agent: Option<&str>) -> String{
+        let mut hasher = Sha256::new();
+        hasher.update(salt.as_bytes());
+        hasher.update(Utc::now().format("%Y-%m-%d").to_string().as_bytes());
+        hasher.update(ip.to_string().as_bytes());
+        hasher.update(user_agent.unwrap_or_default().as_bytes());
+        format!("{:x}", hasher.finalize())[..32].to_string()
+    }
+}
+
+/// Limits how many buckets a series can have, going back from its end.
+const MAX_BUCKETS: i32 = 400;
+const TOP_LIMIT: i64 = 10;
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Bucket{
+    Hour,
+    Day,
+    Week,
+}
+
+impl FromStr for Bucket{
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err>{
+        match value{
+            "hour" => Ok(Bucket::Hour),
+            "day" => Ok(Bucket::Day),
+            "week" => Ok(Bucket::Week),
+            _ => Err(format!("Unknown bucket '{}'", value)),
+        }
+    }
+}
+
+impl fmt::Display for Bucket{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result{
+        match self{
+            Bucket::Hour => write!(f, "hour"),
+            Bucket::Day => write!(f, "day"),
+            Bucket::Week => write!(f, "week"),
+        }
+    }
+}
+
+impl Bucket{
+    pub fn step(&self) -> Duration{
+        match self{
+            Bucket::Hour => Duration::hours(1),
+            Bucket::Day => Duration::days(1),
+            Bucket::Week => Duration::weeks(1),
+        }
+    }
+
+    /// The range shown when none is asked for.
+    pub fn default_range(&self) -> Duration{
+        match self{
+            Bucket::Hour => Duration::hours(48),
+            Bucket::Day => Duration::days(30),
+            Bucket::Week => Duration::weeks(26),
+        }
+    }
+
+    /// SQL expression with the key of the bucket for `created_at`. It must
+    /// give the same keys as `key`.
+    fn sql(&self) -> &'static str{
+        match self{
+            Bucket::Hour => "strftime('%Y-%m-%d %H:00', created_at)",
+            Bucket::Day => "date(created_at)",
+            Bucket::Week => "date(created_at, '-6 days', 'weekday 1')",
+        }
+    }
+
+    /// The start of the bucket of `moment`, or `None` when it is too far
+    /// from the epoch to be truncated.
+    pub fn truncate(&self, moment: DateTime<Utc>) -> Option<DateTime<Utc>>{
+        let day = moment.duration_trunc(Duration::days(1)).ok()?;
+        match self{
+            Bucket::Hour => moment.duration_trunc(Duration::hours(1)).ok(),
+            Bucket::Day => Some(day),
+            Bucket::Week => day.checked_sub_signed(Duration::days(
+                day.weekday().num_days_from_monday().into())),
+        }
+    }
+
+    pub fn key(&self, moment: DateTime<Utc>) -> Option<String>{
+        let start = self.truncate(moment)?;
+        Some(match self{
+            Bucket::Hour => start.format("%Y-%m-%d %H:00").to_string(),
+            _ => start.format("%Y-%m-%d").to_string(),
+        })
+    }
+
+    /// Every key from `from` to `to`, so empty buckets show up too. It is
+    /// empty when the range can't be truncated.
+    pub fn keys(&self, from: DateTime<Utc>, to: DateTime<Utc>) -> Vec<String>{
+        let first = self.truncate(to)
+            .and_then(|last| last.checked_sub_signed(self.step() * (MAX_BUCKETS - 1)));
+        let mut keys = Vec::new();
+        let mut moment = match first.and_then(|first| self.truncate(from.max(first))){
+            Some(moment) => moment,
+            None => return keys,
+        };
+        while moment < to{
+            match self.key(moment){
+                Some(key) => keys.push(key),
+                None => break,
+            }
+            match moment.checked_add_signed(self.step()){
+                Some(next) => moment = next,
+                None => break,
+            }
+        }
+        keys
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct Count{
+    pub key: String,
+    pub clicks: i64,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct RuleCount{
+    pub rule_id: Option<i64>,
+    pub clicks: i64,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct VariantCount{
+    pub variant_id: Option<i64>,
+    pub clicks: i64,
+}
+
+impl Click{
+    fn from_row(row: SqliteRow) -> Self{
+        Self{
+            id: row.get("id"),
+            url_id: row.get("url_id"),
+            referrer: row.get("referrer"),
+            user_agent: row.get("user_agent"),
+            accept_language: row.get("accept_language"),
+            client_hash: row.get("client_hash"),
+            rule_id: row.get("rule_id"),
+            variant_id: row.get("variant_id"),
+            created_at: row.get("created_at"),
+        }
+    }
+
+    /// Records a visit, along with the rule or the variant that chose its
+    /// destination.
+    pub async fn create(pool: &SqlitePool, url_id: i64, rule_id: Option<i64>,
+            variant_id: Option<i64>, visitor: &Visitor) -> Result<Self, sqlx::Error>{
+        info!("Click create");
+        let sql = "INSERT INTO clicks (url_id, referrer, user_agent,
+                   accept_language, client_hash, rule_id, variant_id, created_at)
+                   VALUES($1, $2, $3, $4, $5, $6, $7, $8) RETURNING *";
+        debug!("Query: {}", sql);
+        query(sql)
+            .bind(url_id)
+            .bind(&visitor.referrer)
+            .bind(&visitor.user_agent)
+            .bind(&visitor.accept_language)
+            .bind(&visitor.client_hash)
+            .bind(rule_id)
+            .bind(variant_id)
+            .bind(Utc::now())
+            .map(Self::from_row)
+            .fetch_one(pool)
+            .await
+    }
+
+    /// The latest clicks of the range, up to `limit`, newest first.
+    pub async fn read_for_url(pool: &SqlitePool, url_id: i64, from: DateTime<Utc>,
+            to: DateTime<Utc>, limit: i64) -> Result<Vec<Self>, sqlx::Error>{
+        info!("Click read_for_url");
+        let sql = "SELECT * FROM clicks WHERE url_id = $1
+                   AND created_at >= $2 AND created_at < $3
+                   ORDER BY created_at DESC, id DESC LIMIT $4";
+        debug!("Query: {}", sql);
+        query(sql)
+            .bind(url_id)
+            .bind(from)
+            .bind(to)
+            .bind(limit)
+            .map(Self::from_row)
+            .fetch_all(pool)
+            .await
+    }
+
+    pub async fn count_for_url(pool: &SqlitePool, url_id: i64, from: DateTime<Utc>,
+            to: DateTime<Utc>) -> Result<i64, sqlx::Error>{
+        info!("Click count_for_url");
+        let sql = "SELECT count(*) FROM clicks WHERE url_id = $1
+                   AND created_at >= $2 AND created_at < $3";
+        debug!("Query: {}", sql);
+        query(sql)
+            .bind(url_id)
+            .bind(from)
+            .bind(to)
+            .map(|row: SqliteRow| -> i64 {row.get(0)})
+            .fetch_one(pool)
+            .await
+    }
+
+    /// Clicks per bucket, with a zero for the buckets without any.
+    pub async fn series_for_url(pool: &SqlitePool, url_id: i64, bucket: Bucket,
+            from: DateTime<Utc>, to: DateTime<Utc>) -> Result<Vec<Count>, sqlx::Error>{
+        info!("Click series_for_url");
+        let sql = format!("SELECT {} AS key, count(*) AS clicks FROM clicks
+                   WHERE url_id = $1 AND created_at >= $2 AND created_at < $3
+                   GROUP BY key", bucket.sql());
+        debug!("Query: {}", sql);
+        let counts: Vec<Count> = query(&sql)
+            .bind(url_id)
+            .bind(from)
+            .bind(to)
+            .map(|row: SqliteRow| Count{ key: row.get("key"), clicks: row.get("clicks") })
+            .fetch_all(pool)
+            .await?;
+        Ok(bucket.keys(from, to)
+            .into_iter()
+            .map(|key| {
+                let clicks = counts.iter()
+                    .find(|count| count.key == key)
+                    .map(|count| count.clicks)
+                    .unwrap_or(0);
+                Count{ key, clicks }
+            })
+            .collect())
+    }
+
+    pub async fn top_referrers(pool: &SqlitePool, url_id: i64, from: DateTime<Utc>,
+            to: DateTime<Utc>) -> Result<Vec<Count>, sqlx::Error>{
+        info!("Click top_referrers");
+        Self::top(pool, "referrer", url_id, from, to).await
+    }
+
+    pub async fn top_user_agents(pool: &SqlitePool, url_id: i64, from: DateTime<Utc>,
+            to: DateTime<Utc>) -> Result<Vec<Count>, sqlx::Error>{
+        info!("Click top_user_agents");
+        Self::top(pool, "user_agent", url_id, from, to).await
+    }
+
+    /// Clicks by the rule that chose their destination, `None` being the
+    /// ones that went to the url itself.
+    pub async fn count_by_rule(pool: &SqlitePool, url_id: i64, from: DateTime<Utc>,
+            to: DateTime<Utc>) -> Result<Vec<RuleCount>, sqlx::Error>{
+        info!("Click count_by_rule");
+        let sql = "SELECT rule_id, count(*) AS clicks FROM clicks
+                   WHERE url_id = $1 AND created_at >= $2 AND created_at < $3
+                   GROUP BY rule_id ORDER BY rule_id";
+        debug!("Query: {}", sql);
+        query(sql)
+            .bind(url_id)
+            .bind(from)
+            .bind(to)
+            .map(|row: SqliteRow| RuleCount{ rule_id: row.get("rule_id"), clicks: row.get("clicks") })
+            .fetch_all(pool)
+            .await
+    }
+
+    /// Clicks by the variant they were sent to, `None` being the ones that
+    /// went elsewhere.
+    pub async fn count_by_variant(pool: &SqlitePool, url_id: i64, from: DateTime<Utc>,
+            to: DateTime<Utc>) -> Result<Vec<VariantCount>, sqlx::Error>{
+        info!("Click count_by_variant");
+        let sql = "SELECT variant_id, count(*) AS clicks FROM clicks
+                   WHERE url_id = $1 AND created_at >= $2 AND created_at < $3
+                   GROUP BY variant_id ORDER BY variant_id";
+        debug!("Query: {}", sql);
+        query(sql)
+            .bind(url_id)
+            .bind(from)
+            .bind(to)
+            .map(|row: SqliteRow| VariantCount{ variant_id: row.get("variant_id"), clicks: row.get("clicks") })
+            .fetch_all(pool)
+            .await
+    }
+
+    async fn top(pool: &SqlitePool, column: &'static str, url_id: i64,
+            from: DateTime<Utc>, to: DateTime<Utc>) -> Result<Vec<Count>, sqlx::Error>{
+        let sql = format!("SELECT COALESCE(NULLIF({}, ''), '(none)') AS key,
+                   count(*) AS clicks FROM clicks
+                   WHERE url_id = $1 AND created_at >= $2 AND created_at < $3
+                   GROUP BY key ORDER BY clicks DESC, key LIMIT $4", column);
+        debug!("Query: {}", sql);
+        query(&sql)
+            .bind(url_id)
+            .bind(from)
+            .bind(to)
+            .bind(TOP_LIMIT)
+            .map(|row: SqliteRow| Count{ key: row.get("key"), clicks: row.get("clicks") })
+            .fetch_all(pool)
+            .await
+    }
+}
+
+#[cfg(test)]
+mod click_test {
+    use sqlx::{
+        self,
+        Pool,
+        sqlite::{
+            Sqlite,
+            SqlitePoolOptions,
+        },
+        migrate::{
+            Migrator,
+            MigrateDatabase
+        }
+    };
+    use std::{env, path::Path, net::{IpAddr, Ipv4Addr}};
+    use chrono::{Duration, TimeZone, Utc};
+    use super::{Bucket, Click, Count, RuleCount, VariantCount, Visitor};
+    use crate::model::url::{Url, UrlOptions};
+
+    async fn setup(db: &str) -> Pool<Sqlite>{
+        let db_url = format!("sqlite:{}", db);
+        teardown(db).await;
+        if !sqlx::Sqlite::database_exists(&db_url).await.unwrap(){
+            sqlx::Sqlite::create_database(&db_url).await.unwrap();
+        }
+        let crate_dir = env::var("CARGO_MANIFEST_DIR").unwrap();
+        let migrations = Path::new(&crate_dir).join("./migrations");
+        let pool = SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect(&db_url)
+            .await
+            .expect("Pool failed");
+
+        Migrator::new(migrations)
+            .await
+            .unwrap()
+            .run(&pool)
+            .await
+        .unwrap();
+        pool
+    }
+
+    #[allow(unused_must_use)]
+    async fn teardown(db: &str) {
+        tokio::fs::remove_file(db).await;
+        tokio::fs::remove_file(format!("{}-shm", db)).await;
+        tokio::fs::remove_file(format!("{}-wal", db)).await;
+    }
+
+    #[test]
+    fn test_client_hash(){
+        let ip = IpAddr::V4(Ipv4Addr::new(192, 168, 1, 2));
+        let hash = Visitor::client_hash("salt", &ip, Some("curl"));
+        assert!(hash == Visitor::client_hash("salt", &ip, Some("curl")));
+        assert!(hash != Visitor::client_hash("other", &ip, Some("curl")));
+        assert!(!hash.contains("192.168"));
+    }
+
+    #[tokio::test]
+    async fn test_read_for_url(){
+        let db = "test-click-read-for-url.db";
+        // Start and prepare
+        let pool = setup(db).await;
+        // Test
+        let url = Url::create(&pool, "https://atareao.es", &UrlOptions::default()).await.unwrap();
+        let other = Url::create(&pool, "https://google.es", &UrlOptions::default()).await.unwrap();
+        let visitor = Visitor{
+            referrer: Some("https://mastodon.social".to_string()),
+            client_hash: "hash".to_string(),
+            ..Default::default()
+        };
+        Click::create(&pool, url.get_id(), None, None, &visitor).await.unwrap();
+        Click::create(&pool, url.get_id(), None, None, &visitor).await.unwrap();
+        Click::create(&pool, other.get_id(), None, None, &visitor).await.unwrap();
+        let from = Utc::now() - Duration::hours(1);
+        let to = Utc::now() + Duration::hours(1);
+        let clicks = Click::read_for_url(&pool, url.get_id(), from, to, 10).await.unwrap();
+        assert!(clicks.len() == 2);
+        assert!(clicks[0].referrer.as_deref() == Some("https://mastodon.social"));
+        assert!(clicks[0].id > clicks[1].id);
+        assert!(Click::read_for_url(&pool, url.get_id(), from, to, 1).await.unwrap().len() == 1);
+        assert!(Click::count_for_url(&pool, url.get_id(), from, to).await.unwrap() == 2);
+        assert!(Click::count_for_url(&pool, url.get_id(), to, to).await.unwrap() == 0);
+        // End and Clean
+        teardown(db).await;
+    }
+
+    #[test]
+    fn test_bucket_keys(){
+        let to = Utc.with_ymd_and_hms(2026, 10, 18, 10, 30, 0).unwrap();
+        let keys = Bucket::Hour.keys(to - Duration::hours(2), to);
+        assert!(keys == vec!["2026-10-18 08:00", "2026-10-18 09:00", "2026-10-18 10:00"]);
+        let keys = Bucket::Day.keys(to - Duration::days(1), to);
+        assert!(keys == vec!["2026-10-17", "2026-10-18"]);
+        // 2026-10-18 is a sunday
+        assert!(Bucket::Week.key(to).as_deref() == Some("2026-10-12"));
+        assert!(Bucket::Hour.keys(to - Duration::days(365), to).len() == 400);
+        let far = Utc.with_ymd_and_hms(2300, 1, 1, 0, 0, 0).unwrap();
+        assert!(Bucket::Day.truncate(far).is_none());
+        assert!(Bucket::Day.keys(far - Duration::days(1), far).is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_series_for_url(){
+        let db = "test-click-series-for-url.db";
+        // Start and prepare
+        let pool = setup(db).await;
+        // Test
+        let url = Url::create(&pool, "https://atareao.es", &UrlOptions::default()).await.unwrap();
+        let visitor = Visitor{
+            referrer: Some("https://mastodon.social".to_string()),
+            client_hash: "hash".to_string(),
+            ..Default::default()
+        };
+        Click::create(&pool, url.get_id(), None, None, &visitor).await.unwrap();
+        Click::create(&pool, url.get_id(), Some(7), None, &Visitor::default()).await.unwrap();
+        let to = Utc::now() + Duration::hours(1);
+        for bucket in [Bucket::Hour, Bucket::Day, Bucket::Week]{
+            let from = to - bucket.default_range();
+            let series = Click::series_for_url(&pool, url.get_id(), bucket, from, to)
+                .await
+                .unwrap();
+            assert!(series.iter().map(|count| count.clicks).sum::<i64>() == 2);
+            assert!(Some(&series.last().unwrap().key) == bucket.key(to).as_ref());
+        }
+        let from = to - Duration::days(1);
+        let referrers = Click::top_referrers(&pool, url.get_id(), from, to).await.unwrap();
+        assert!(referrers == vec![
+            Count{ key: "(none)".to_string(), clicks: 1 },
+            Count{ key: "https://mastodon.social".to_string(), clicks: 1 },
+        ]);
+        let rules = Click::count_by_rule(&pool, url.get_id(), from, to).await.unwrap();
+        assert!(rules == vec![
+            RuleCount{ rule_id: None, clicks: 1 },
+            RuleCount{ rule_id: Some(7), clicks: 1 },
+        ]);
+        Click::create(&pool, url.get_id(), None, Some(3), &Visitor::default()).await.unwrap();
+        let variants = Click::count_by_variant(&pool, url.get_id(), from, to).await.unwrap();
+        assert!(variants == vec![
+            VariantCount{ variant_id: None, clicks: 2 },
+            VariantCount{ variant_id: Some(3), clicks: 1 },
+        ]);
+        // End and Clean
+        teardown(db).await;
+    }
+}
@@ -0,0 +1,235 @@
+use serde::{Serialize, Deserialize};
+use sqlx::{sqlite::{Sqlite, SqlitePool, SqliteRow}, query, Executor, Row};
+use chrono::{DateTime, Utc};
+use tracing::{debug, info};
+
+use super::parameters::double_option;
+
+
+/// A host served besides the one of `url` in `config.yml`, with its own
+/// links. Its root and its unknown paths can send the visitors somewhere
+/// else instead of to the form and the error page.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Domain{
+    id: i64,
+    host: String,
+    root_url: Option<String>,
+    not_found_url: Option<String>,
+    created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct NewDomain{
+    pub host: String,
+    #[serde(default)]
+    pub root_url: Option<String>,
+    #[serde(default)]
+    pub not_found_url: Option<String>,
+}
+
+/// Changes to a domain. A field set to `null` is cleared, a missing one is
+/// kept as it is.
+#[derive(Debug, Default, Deserialize)]
+pub struct DomainPatch{
+    #[serde(default, deserialize_with = "double_option")]
+    pub root_url: Option<Option<String>>,
+    #[serde(default, deserialize_with = "double_option")]
+    pub not_found_url: Option<Option<String>>,
+}
+
+impl Domain{
+    pub fn get_id(&self) -> i64{
+        self.id
+    }
+    pub fn get_root_url(&self) -> Option<&str>{
+        self.root_url.as_deref()
+    }
+    pub fn get_not_found_url(&self) -> Option<&str>{
+        self.not_found_url.as_deref()
+    }
+
+    /// The host of a `Host` header or given by a client, in lower case and
+    /// without the port, if it is a valid one.
+    pub fn normalize(host: &str) -> Option<String>{
+        let host = host.trim().trim_end_matches('.').to_lowercase();
+        let host = match host.rsplit_once(':'){
+            Some((name, port)) if port.chars().all(|c| c.is_ascii_digit()) => name.to_string(),
+            _ => host,
+        };
+        let valid = !host.is_empty()
+            && !host.starts_with(['.', '-'])
+            && host.chars().all(|c| c.is_ascii_alphanumeric() || c == '.' || c == '-');
+        valid.then(|| host)
+    }
+
+    fn from_row(row: SqliteRow) -> Self{
+        Self{
+            id: row.get("id"),
+            host: row.get("host"),
+            root_url: row.get("root_url"),
+            not_found_url: row.get("not_found_url"),
+            created_at: row.get("created_at"),
+        }
+    }
+
+    pub async fn create(pool: &SqlitePool, domain: &NewDomain) -> Result<Self, sqlx::Error>{
+        info!("Domain create");
+        let sql = "INSERT INTO domains (host, root_url, not_found_url, created_at)
+                   VALUES($1, $2, $3, $4) RETURNING *";
+        debug!("Query: {}", sql);
+        query(sql)
+            .bind(&domain.host)
+            .bind(&domain.root_url)
+            .bind(&domain.not_found_url)
+            .bind(Utc::now())
+            .map(Self::from_row)
+            .fetch_one(pool)
+            .await
+    }
+
+    pub async fn read_from_host<'e, E>(executor: E, host: &str) -> Result<Self, sqlx::Error>
+    where E: Executor<'e, Database = Sqlite>{
+        info!("Domain read_from_host");
+        let sql = "SELECT * FROM domains WHERE host = $1";
+        debug!("Query: {}", sql);
+        query(sql)
+            .bind(host)
+            .map(Self::from_row)
+            .fetch_one(executor)
+            .await
+    }
+
+    pub async fn read_all(pool: &SqlitePool) -> Result<Vec<Self>, sqlx::Error>{
+        info!("Domain read_all");
+        let sql = "SELECT * FROM domains ORDER BY host";
+        debug!("Query: {}", sql);
+        query(sql)
+            .map(Self::from_row)
+            .fetch_all(pool)
+            .await
+    }
+
+    pub async fn update(pool: &SqlitePool, id: i64, patch: DomainPatch) -> Result<Self, sqlx::Error>{
+        info!("Domain update");
+        // A missing field is bound as itself, a cleared one as null
+        let sql = "UPDATE domains SET
+                   root_url = CASE WHEN $2 THEN $3 ELSE root_url END,
+                   not_found_url = CASE WHEN $4 THEN $5 ELSE not_found_url END
+                   WHERE id = $1 RETURNING *";
+        debug!("Query: {}", sql);
+        query(sql)
+            .bind(id)
+            .bind(patch.root_url.is_some())
+            .bind(patch.root_url.flatten())
+            .bind(patch.not_found_url.is_some())
+            .bind(patch.not_found_url.flatten())
+            .map(Self::from_row)
+            .fetch_one(pool)
+            .await
+    }
+
+    /// Deletes a domain, which fails while it still has links.
+    pub async fn delete(pool: &SqlitePool, id: i64) -> Result<Self, sqlx::Error>{
+        info!("Domain delete");
+        let sql = "DELETE FROM domains WHERE id = $1 RETURNING *";
+        debug!("Query: {}", sql);
+        query(sql)
+            .bind(id)
+            .map(Self::from_row)
+            .fetch_one(pool)
+            .await
+    }
+}
+
+#[cfg(test)]
+mod domain_test {
+    use sqlx::{
+        self,
+        Pool,
+        sqlite::{
+            Sqlite,
+            SqlitePoolOptions,
+        },
+        migrate::{
+            Migrator,
+            MigrateDatabase
+        }
+    };
+    use std::{env, path::Path};
+    use crate::model::url::{Url, UrlOptions};
+    use super::{Domain, DomainPatch, NewDomain};
+
+    async fn setup(db: &str) -> Pool<Sqlite>{
+        let db_url = format!("sqlite:{}", db);
+        teardown(db).await;
+        if !sqlx::Sqlite::database_exists(&db_url).await.unwrap(){
+            sqlx::Sqlite::create_database(&db_url).await.unwrap();
+        }
+        let crate_dir = env::var("CARGO_MANIFEST_DIR").unwrap();
+        let migrations = Path::new(&crate_dir).join("./migrations");
+        let pool = SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect(&db_url)
+            .await
+            .expect("Pool failed");
+
+        Migrator::new(migrations)
+            .await
+            .unwrap()
+            .run(&pool)
+            .await
+        .unwrap();
+        pool
+    }
+
+    #[allow(unused_must_use)]
+    async fn teardown(db: &str) {
+        tokio::fs::remove_file(db).await;
+        tokio::fs::remove_file(format!("{}-shm", db)).await;
+        tokio::fs::remove_file(format!("{}-wal", db)).await;
+    }
+
+    #[test]
+    fn test_normalize(){
+        assert!(Domain::normalize("Go.Company.com:8080").as_deref() == Some("go.company.com"));
+        assert!(Domain::normalize("brand.link.").as_deref() == Some("brand.link"));
+        assert!(Domain::normalize("").is_none());
+        assert!(Domain::normalize("bad host").is_none());
+        assert!(Domain::normalize("-x.com").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_domains(){
+        let db = "test-domains.db";
+        // Start and prepare
+        let pool = setup(db).await;
+        let new_domain = NewDomain{
+            host: "brand.link".to_string(),
+            root_url: Some("https://brand.com".to_string()),
+            not_found_url: None,
+        };
+        // Test
+        let brand = Domain::create(&pool, &new_domain).await.unwrap();
+        assert!(Domain::create(&pool, &new_domain).await.is_err());
+        let read = Domain::read_from_host(&pool, "brand.link").await.unwrap();
+        assert!(read.get_id() == brand.get_id());
+        let patch = DomainPatch{
+            not_found_url: Some(Some("https://brand.com/404".to_string())),
+            ..Default::default()
+        };
+        let brand = Domain::update(&pool, brand.get_id(), patch).await.unwrap();
+        assert!(brand.get_root_url() == Some("https://brand.com"));
+        assert!(brand.get_not_found_url() == Some("https://brand.com/404"));
+        let patch = DomainPatch{ root_url: Some(None), ..Default::default() };
+        let brand = Domain::update(&pool, brand.get_id(), patch).await.unwrap();
+        assert!(brand.get_root_url().is_none());
+        let options = UrlOptions{ domain_id: Some(brand.get_id()), ..Default::default() };
+        let url = Url::create(&pool, "https://atareao.es", &options).await.unwrap();
+        assert!(Domain::delete(&pool, brand.get_id()).await.is_err());
+        Url::delete(&pool, url.get_id()).await.unwrap();
+        Domain::delete(&pool, brand.get_id()).await.unwrap();
+        assert!(Domain::read_all(&pool).await.unwrap().is_empty());
+        // End and Clean
+        teardown(db).await;
+    }
+}
@@ -1,4 +1,11 @@
 pub mod url;
+pub mod api_key;
+pub mod click;
+pub mod rule;
+pub mod variant;
+pub mod tag;
+pub mod domain;
+pub mod slug;
 pub mod radix;
 pub mod parameters;
 pub mod response;
@@ -1,6 +1,13 @@
 use serde::{de, Deserialize, Deserializer};
 use std::{fmt, str::FromStr};
+use chrono::NaiveDate;
 
+use super::{
+    click::Bucket,
+    url::{Order, Search, Sort},
+};
+
+#[allow(dead_code)]
 #[derive(Debug, Deserialize)]
 pub struct Parameters {
     #[serde(default, deserialize_with = "empty_string_as_none")]
@@ -9,6 +16,127 @@ pub struct Parameters {
     pub message: Option<String>,
 }
 
+#[derive(Debug, Deserialize)]
+pub struct StatsParameters {
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    pub bucket: Option<Bucket>,
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    pub from: Option<NaiveDate>,
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    pub to: Option<NaiveDate>,
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    pub format: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct FormatParameters {
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    pub format: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ListParameters {
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    pub q: Option<String>,
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    pub tag: Option<String>,
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    pub sort: Option<Sort>,
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    pub order: Option<Order>,
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    pub page: Option<i64>,
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    pub per_page: Option<i64>,
+}
+
+impl ListParameters {
+    pub fn get_search(&self) -> Search {
+        Search {
+            query: self.q.clone(),
+            tag: self.tag.as_deref().map(|tag| tag.trim().to_lowercase()),
+            domain: None,
+            sort: self.sort.unwrap_or_default(),
+            order: self.order.unwrap_or_default(),
+        }
+    }
+    pub fn get_page(&self) -> i64 {
+        self.page.unwrap_or(1).max(1)
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ExportParameters {
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    pub format: Option<ExportFormat>,
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    pub tag: Option<String>,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum ExportFormat {
+    #[default]
+    Csv,
+    Ndjson,
+}
+
+impl FromStr for ExportFormat {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "csv" => Ok(ExportFormat::Csv),
+            "ndjson" | "json" => Ok(ExportFormat::Ndjson),
+            _ => Err(format!("Unknown format '{}'", value)),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct QrParameters {
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    pub size: Option<u32>,
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    pub margin: Option<u32>,
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    pub ec: Option<QrCorrection>,
+}
+
+/// Error correction level of a QR code, from the 7% of `Low` to the 30%
+/// of `High`.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum QrCorrection {
+    Low,
+    #[default]
+    Medium,
+    Quartile,
+    High,
+}
+
+impl FromStr for QrCorrection {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.to_lowercase().as_str() {
+            "l" | "low" => Ok(QrCorrection::Low),
+            "m" | "medium" => Ok(QrCorrection::Medium),
+            "q" | "quartile" => Ok(QrCorrection::Quartile),
+            "h" | "high" => Ok(QrCorrection::High),
+            _ => Err(format!("Unknown error correction level '{}'", value)),
+        }
+    }
+}
+
+/// Serde deserialization decorator to tell a missing field, None, from a
+/// null one, Some(None),
+pub fn double_option<'de, D, T>(de: D) -> Result<Option<Option<T>>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    Option::<T>::deserialize(de).map(Some)
+}
+
 /// Serde deserialization decorator to map empty Strings to None,
 fn empty_string_as_none<'de, D, T>(de: D) -> Result<Option<T>, D::Error>
 where
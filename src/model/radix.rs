@@ -1,32 +1,144 @@
+use std::fmt;
 
-const RADIX: u32 = 36;
+pub const BASE36: &str = "0123456789abcdefghijklmnopqrstuvwxyz";
+pub const BASE62: &str = "0123456789abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ";
 
-pub fn to_d36(mut x: u32) -> String {
+
+/// Why an id can't be written as a slug, or a slug read as an id.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CodecError{
+    Empty,
+    Negative(i64),
+    InvalidChar(char),
+    Overflow,
+    InvalidAlphabet,
+}
+
+impl fmt::Display for CodecError{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result{
+        match self{
+            CodecError::Empty => write!(f, "The slug is empty"),
+            CodecError::Negative(id) => write!(f, "The id {} is negative", id),
+            CodecError::InvalidChar(c) => write!(f, "The slug has an invalid character '{}'", c),
+            CodecError::Overflow => write!(f, "The slug is too long for an id"),
+            CodecError::InvalidAlphabet => write!(f, "The alphabet needs at least two different ascii characters"),
+        }
+    }
+}
+
+impl std::error::Error for CodecError{}
+
+/// Writes the ids of the links as slugs and reads them back, over the whole
+/// range of non negative `i64`.
+pub trait SlugCodec{
+    fn encode(&self, id: i64) -> Result<String, CodecError>;
+    fn decode(&self, slug: &str) -> Result<i64, CodecError>;
+}
+
+/// The slugs of the links named by their ids. Reads upper case too, as it
+/// always did.
+pub struct Base36;
+
+pub struct Base62;
+
+/// Any alphabet, in the order of its digits.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Alphabet(Vec<u8>);
+
+impl Alphabet{
+    pub fn new(digits: &[u8]) -> Result<Self, CodecError>{
+        let mut unique = digits.to_vec();
+        unique.sort_unstable();
+        unique.dedup();
+        if unique.len() < 2 || unique.len() != digits.len() || !digits.is_ascii(){
+            return Err(CodecError::InvalidAlphabet);
+        }
+        Ok(Self(digits.to_vec()))
+    }
+}
+
+impl SlugCodec for Base36{
+    fn encode(&self, id: i64) -> Result<String, CodecError>{
+        encode(BASE36.as_bytes(), id)
+    }
+    fn decode(&self, slug: &str) -> Result<i64, CodecError>{
+        decode(BASE36.as_bytes(), &slug.to_ascii_lowercase())
+    }
+}
+
+impl SlugCodec for Base62{
+    fn encode(&self, id: i64) -> Result<String, CodecError>{
+        encode(BASE62.as_bytes(), id)
+    }
+    fn decode(&self, slug: &str) -> Result<i64, CodecError>{
+        decode(BASE62.as_bytes(), slug)
+    }
+}
+
+impl SlugCodec for Alphabet{
+    fn encode(&self, id: i64) -> Result<String, CodecError>{
+        encode(&self.0, id)
+    }
+    fn decode(&self, slug: &str) -> Result<i64, CodecError>{
+        decode(&self.0, slug)
+    }
+}
+
+fn encode(digits: &[u8], id: i64) -> Result<String, CodecError>{
+    if id < 0{
+        return Err(CodecError::Negative(id));
+    }
+    let base = digits.len() as i64;
+    let mut value = id;
     let mut result = vec![];
-    loop {
-        let m = x % RADIX;
-        x = x / RADIX;
-        result.push(std::char::from_digit(m, RADIX).unwrap());
-        if x == 0 {
+    loop{
+        result.push(char::from(digits[(value % base) as usize]));
+        value /= base;
+        if value == 0{
             break;
         }
     }
-    result.into_iter().rev().collect()
+    Ok(result.into_iter().rev().collect())
+}
+
+fn decode(digits: &[u8], slug: &str) -> Result<i64, CodecError>{
+    if slug.is_empty(){
+        return Err(CodecError::Empty);
+    }
+    let base = digits.len() as i64;
+    slug.chars().try_fold(0i64, |value, c|{
+        let digit = u8::try_from(c).ok()
+            .and_then(|c| digits.iter().position(|digit| *digit == c))
+            .ok_or(CodecError::InvalidChar(c))?;
+        value.checked_mul(base)
+            .and_then(|value| value.checked_add(digit as i64))
+            .ok_or(CodecError::Overflow)
+    })
 }
-pub fn from_d36(x: &str) -> u32{
-    let mut value: u32 = 0;
-    let n: u32 = x.len().try_into().unwrap();
-    for (pos, char) in x.chars().enumerate(){
-        let upos: u32 = pos.try_into().unwrap();
-        let realpos = n - upos - 1;
-        let val: u32 = char.to_digit(RADIX).unwrap().try_into().unwrap();
-        value += RADIX.pow(realpos) * val;
+
+#[test]
+fn round_trips(){
+    let custom = Alphabet::new(b"01").unwrap();
+    for id in [0, 1, 35, 36, 1234, i64::from(u32::MAX) + 1, i64::MAX]{
+        assert_eq!(Base36.decode(&Base36.encode(id).unwrap()), Ok(id));
+        assert_eq!(Base62.decode(&Base62.encode(id).unwrap()), Ok(id));
+        assert_eq!(custom.decode(&custom.encode(id).unwrap()), Ok(id));
     }
-    value
+    assert_eq!(Base36.encode(1234).unwrap(), "ya");
+    assert_eq!(Base62.encode(61).unwrap(), "Z");
+    assert_eq!(custom.encode(5).unwrap(), "101");
+    assert_eq!(Base36.encode(-1), Err(CodecError::Negative(-1)));
 }
 
 #[test]
-fn sample(){
-    println!("{}", from_d36("y"));
-    println!("{}", to_d36(1234));
+fn decode_invalid(){
+    assert_eq!(Base36.decode("ya"), Ok(1234));
+    assert_eq!(Base36.decode("YA"), Ok(1234));
+    assert_eq!(Base36.decode("favicon.png"), Err(CodecError::InvalidChar('.')));
+    assert_eq!(Base36.decode("\u{1F600}"), Err(CodecError::InvalidChar('\u{1F600}')));
+    assert_eq!(Base36.decode("zzzzzzzzzzzzzz"), Err(CodecError::Overflow));
+    assert_eq!(Base36.decode(""), Err(CodecError::Empty));
+    assert_eq!(Base62.decode("a-b"), Err(CodecError::InvalidChar('-')));
+    assert!(Alphabet::new(b"aa").is_err());
+    assert!(Alphabet::new(b"a").is_err());
 }
@@ -12,7 +12,7 @@ impl Response{
         Self{
             status,
             message: message.to_string(),
-            value: message.to_string(),
+            value: value.to_string(),
         }
     }
 }
@@ -0,0 +1,328 @@
+use std::str::FromStr;
+
+use serde::{Serialize, Deserialize};
+use sqlx::{sqlite::{SqlitePool, SqliteRow}, query, Row};
+use chrono::{DateTime, Utc};
+use tracing::{debug, info};
+use woothee::parser::Parser;
+
+
+/// Family of the operating system of a visitor.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Os{
+    Ios,
+    Android,
+    Windows,
+    Macos,
+    Linux,
+    Chromeos,
+}
+
+impl Os{
+    fn as_str(&self) -> &'static str{
+        match self{
+            Os::Ios => "ios",
+            Os::Android => "android",
+            Os::Windows => "windows",
+            Os::Macos => "macos",
+            Os::Linux => "linux",
+            Os::Chromeos => "chromeos",
+        }
+    }
+
+    /// The family of an operating system as named by woothee.
+    fn from_woothee(os: &str) -> Option<Self>{
+        match os{
+            "iPhone" | "iPad" | "iPod" | "iOS" => Some(Os::Ios),
+            "Android" => Some(Os::Android),
+            "Mac OSX" | "Mac OS Classic" => Some(Os::Macos),
+            "Linux" => Some(Os::Linux),
+            "ChromeOS" => Some(Os::Chromeos),
+            os if os.starts_with("Windows") => Some(Os::Windows),
+            _ => None,
+        }
+    }
+}
+
+impl FromStr for Os{
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err>{
+        match value{
+            "ios" => Ok(Os::Ios),
+            "android" => Ok(Os::Android),
+            "windows" => Ok(Os::Windows),
+            "macos" => Ok(Os::Macos),
+            "linux" => Ok(Os::Linux),
+            "chromeos" => Ok(Os::Chromeos),
+            _ => Err(format!("Unknown os '{}'", value)),
+        }
+    }
+}
+
+/// Class of the device of a visitor.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Device{
+    Desktop,
+    Mobile,
+    Tablet,
+}
+
+impl Device{
+    fn as_str(&self) -> &'static str{
+        match self{
+            Device::Desktop => "desktop",
+            Device::Mobile => "mobile",
+            Device::Tablet => "tablet",
+        }
+    }
+}
+
+impl FromStr for Device{
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err>{
+        match value{
+            "desktop" => Ok(Device::Desktop),
+            "mobile" => Ok(Device::Mobile),
+            "tablet" => Ok(Device::Tablet),
+            _ => Err(format!("Unknown device '{}'", value)),
+        }
+    }
+}
+
+/// What the User-Agent of a visit tells about the client.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Agent{
+    pub os: Option<Os>,
+    pub device: Option<Device>,
+    pub bot: bool,
+}
+
+impl Agent{
+    pub fn parse(user_agent: &str) -> Self{
+        let result = match Parser::new().parse(user_agent){
+            Some(result) => result,
+            None => return Self::default(),
+        };
+        let os = Os::from_woothee(result.os);
+        // Android tablets are the ones that don't say they are mobile
+        let device = match result.category{
+            "pc" => Some(Device::Desktop),
+            "smartphone" | "mobilephone" if result.os == "iPad" => Some(Device::Tablet),
+            "smartphone" if os == Some(Os::Android) && !user_agent.contains("Mobile") => {
+                Some(Device::Tablet)
+            },
+            "smartphone" | "mobilephone" => Some(Device::Mobile),
+            _ => None,
+        };
+        Self{
+            os,
+            device,
+            bot: result.category == "crawler",
+        }
+    }
+}
+
+/// Sends the visits of the clients that match every condition set in it to
+/// its own destination instead of the one of the url. The rules of a url
+/// are tried in order and the first one that matches wins.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Rule{
+    id: i64,
+    url_id: i64,
+    position: i64,
+    os: Option<Os>,
+    device: Option<Device>,
+    bot: Option<bool>,
+    src: String,
+    created_at: DateTime<Utc>,
+}
+
+/// The conditions and the destination of a new rule.
+#[derive(Debug, Deserialize)]
+pub struct NewRule{
+    #[serde(default)]
+    pub os: Option<Os>,
+    #[serde(default)]
+    pub device: Option<Device>,
+    #[serde(default)]
+    pub bot: Option<bool>,
+    pub src: String,
+}
+
+impl NewRule{
+    pub fn has_conditions(&self) -> bool{
+        self.os.is_some() || self.device.is_some() || self.bot.is_some()
+    }
+}
+
+impl Rule{
+    pub fn get_id(&self) -> i64{
+        self.id
+    }
+    pub fn get_src(&self) -> &str{
+        &self.src
+    }
+
+    pub fn matches(&self, agent: &Agent) -> bool{
+        self.os.map_or(true, |os| agent.os == Some(os))
+            && self.device.map_or(true, |device| agent.device == Some(device))
+            && self.bot.map_or(true, |bot| agent.bot == bot)
+    }
+
+    fn from_row(row: SqliteRow) -> Self{
+        Self{
+            id: row.get("id"),
+            url_id: row.get("url_id"),
+            position: row.get("position"),
+            os: row.get::<Option<String>, _>("os")
+                .and_then(|value| value.parse().ok()),
+            device: row.get::<Option<String>, _>("device")
+                .and_then(|value| value.parse().ok()),
+            bot: row.get("bot"),
+            src: row.get("src"),
+            created_at: row.get("created_at"),
+        }
+    }
+
+    /// Adds a rule after the ones the url already has.
+    pub async fn create(pool: &SqlitePool, url_id: i64, rule: &NewRule) -> Result<Self, sqlx::Error>{
+        info!("Rule create");
+        let sql = "INSERT INTO rules (url_id, position, os, device, bot, src, created_at)
+                   VALUES($1, (SELECT COALESCE(MAX(position), 0) + 1 FROM rules WHERE url_id = $1),
+                   $2, $3, $4, $5, $6) RETURNING *";
+        debug!("Query: {}", sql);
+        query(sql)
+            .bind(url_id)
+            .bind(rule.os.as_ref().map(Os::as_str))
+            .bind(rule.device.as_ref().map(Device::as_str))
+            .bind(rule.bot)
+            .bind(&rule.src)
+            .bind(Utc::now())
+            .map(Self::from_row)
+            .fetch_one(pool)
+            .await
+    }
+
+    /// The rules of a url, in the order they are tried.
+    pub async fn read_for_url(pool: &SqlitePool, url_id: i64) -> Result<Vec<Self>, sqlx::Error>{
+        info!("Rule read_for_url");
+        let sql = "SELECT * FROM rules WHERE url_id = $1 ORDER BY position, id";
+        debug!("Query: {}", sql);
+        query(sql)
+            .bind(url_id)
+            .map(Self::from_row)
+            .fetch_all(pool)
+            .await
+    }
+
+    pub async fn delete(pool: &SqlitePool, url_id: i64, id: i64) -> Result<Self, sqlx::Error>{
+        info!("Rule delete");
+        let sql = "DELETE FROM rules WHERE id = $1 AND url_id = $2
+                   RETURNING *";
+        debug!("Query: {}", sql);
+        query(sql)
+            .bind(id)
+            .bind(url_id)
+            .map(Self::from_row)
+            .fetch_one(pool)
+            .await
+    }
+}
+
+#[cfg(test)]
+mod rule_test {
+    use sqlx::{
+        self,
+        Pool,
+        sqlite::{
+            Sqlite,
+            SqlitePoolOptions,
+        },
+        migrate::{
+            Migrator,
+            MigrateDatabase
+        }
+    };
+    use std::{env, path::Path};
+    use crate::model::url::{Url, UrlOptions};
+    use super::{Agent, Device, NewRule, Os, Rule};
+
+    const IPHONE: &str = "Mozilla/5.0 (iPhone; CPU iPhone OS 16_5 like Mac OS X) AppleWebKit/605.1.15 (KHTML, like Gecko) Version/16.5 Mobile/15E148 Safari/604.1";
+    const ANDROID: &str = "Mozilla/5.0 (Linux; Android 13; Pixel 7) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/116.0.0.0 Mobile Safari/537.36";
+    const ANDROID_TABLET: &str = "Mozilla/5.0 (Linux; Android 12; SM-X700) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/116.0.0.0 Safari/537.36";
+    const WINDOWS: &str = "Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/116.0.0.0 Safari/537.36";
+    const GOOGLEBOT: &str = "Mozilla/5.0 (compatible; Googlebot/2.1; +http://www.google.com/bot.html)";
+
+    async fn setup(db: &str) -> Pool<Sqlite>{
+        let db_url = format!("sqlite:{}", db);
+        teardown(db).await;
+        if !sqlx::Sqlite::database_exists(&db_url).await.unwrap(){
+            sqlx::Sqlite::create_database(&db_url).await.unwrap();
+        }
+        let crate_dir = env::var("CARGO_MANIFEST_DIR").unwrap();
+        let migrations = Path::new(&crate_dir).join("./migrations");
+        let pool = SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect(&db_url)
+            .await
+            .expect("Pool failed");
+
+        Migrator::new(migrations)
+            .await
+            .unwrap()
+            .run(&pool)
+            .await
+        .unwrap();
+        pool
+    }
+
+    #[allow(unused_must_use)]
+    async fn teardown(db: &str) {
+        tokio::fs::remove_file(db).await;
+        tokio::fs::remove_file(format!("{}-shm", db)).await;
+        tokio::fs::remove_file(format!("{}-wal", db)).await;
+    }
+
+    #[test]
+    fn test_agent(){
+        let agent = Agent::parse(IPHONE);
+        assert!(agent.os == Some(Os::Ios) && agent.device == Some(Device::Mobile) && !agent.bot);
+        let agent = Agent::parse(ANDROID);
+        assert!(agent.os == Some(Os::Android) && agent.device == Some(Device::Mobile));
+        assert!(Agent::parse(ANDROID_TABLET).device == Some(Device::Tablet));
+        let agent = Agent::parse(WINDOWS);
+        assert!(agent.os == Some(Os::Windows) && agent.device == Some(Device::Desktop));
+        assert!(Agent::parse(GOOGLEBOT).bot);
+        assert!(Agent::parse("") == Agent::default());
+    }
+
+    #[tokio::test]
+    async fn test_rules(){
+        let db = "test-rules.db";
+        // Start and prepare
+        let pool = setup(db).await;
+        let url = Url::create(&pool, "https://atareao.es", &UrlOptions::default()).await.unwrap();
+        let new_rule = |os, device, src: &str| NewRule{ os, device, bot: None, src: src.to_string() };
+        // Test
+        let ios = Rule::create(&pool, url.get_id(),
+            &new_rule(Some(Os::Ios), None, "https://apps.apple.com")).await.unwrap();
+        let mobile = Rule::create(&pool, url.get_id(),
+            &new_rule(None, Some(Device::Mobile), "https://m.atareao.es")).await.unwrap();
+        let rules = Rule::read_for_url(&pool, url.get_id()).await.unwrap();
+        assert!(rules.len() == 2 && rules[0].position < rules[1].position);
+        let first = |agent: &Agent| rules.iter().find(|rule| rule.matches(agent)).map(Rule::get_id);
+        assert!(first(&Agent::parse(IPHONE)) == Some(ios.get_id()));
+        assert!(first(&Agent::parse(ANDROID)) == Some(mobile.get_id()));
+        assert!(first(&Agent::parse(WINDOWS)).is_none());
+        assert!(Rule::delete(&pool, url.get_id() + 1, ios.get_id()).await.is_err());
+        Rule::delete(&pool, url.get_id(), ios.get_id()).await.unwrap();
+        Url::delete(&pool, url.get_id()).await.unwrap();
+        assert!(Rule::read_for_url(&pool, url.get_id()).await.unwrap().is_empty());
+        // End and Clean
+        teardown(db).await;
+    }
+}
@@ -0,0 +1,252 @@
+use rand::seq::SliceRandom;
+use serde::{Serialize, Deserialize};
+
+use super::radix::{Alphabet, Base62, CodecError, SlugCodec, BASE62};
+
+const DEFAULT_LENGTH: usize = 7;
+const DEFAULT_MIN_LENGTH: usize = 6;
+const MIN_ALPHABET: usize = 16;
+const MAX_LENGTH: usize = 16;
+/// Keeps the shift of obfuscated ids, see `Obfuscator`, far from overflowing.
+const MAX_MIN_LENGTH: usize = 10;
+/// How many names are tried for a link before giving up on finding a free
+/// one.
+pub const MAX_ATTEMPTS: usize = 10;
+
+
+/// How the links without a custom slug are named. Their names are saved, so
+/// the links keep them when the strategy changes, and the ones named by
+/// their base-36 ids keep resolving from them.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+pub enum SlugStrategy{
+    /// The id of the link in base-36, or in the shorter base-62. Easy to
+    /// walk through.
+    Sequential{
+        #[serde(default)]
+        base62: bool,
+    },
+    /// The id shuffled with a secret salt, Hashids style, of at least
+    /// `min_length` characters. Only the salt can read the id back, see
+    /// `Obfuscator`.
+    Obfuscated{
+        salt: String,
+        #[serde(default = "default_alphabet")]
+        alphabet: String,
+        #[serde(default = "default_min_length")]
+        min_length: usize,
+    },
+    /// `length` random characters of the alphabet, drawn again when they
+    /// are taken.
+    Random{
+        #[serde(default = "default_length")]
+        length: usize,
+        #[serde(default = "default_alphabet")]
+        alphabet: String,
+    },
+}
+
+impl Default for SlugStrategy{
+    fn default() -> Self{
+        SlugStrategy::Sequential{ base62: false }
+    }
+}
+
+fn default_alphabet() -> String{
+    BASE62.to_string()
+}
+
+fn default_length() -> usize{
+    DEFAULT_LENGTH
+}
+
+fn default_min_length() -> usize{
+    DEFAULT_MIN_LENGTH
+}
+
+impl SlugStrategy{
+    pub fn validate(&self) -> Result<(), String>{
+        let (alphabet, length, max) = match self{
+            SlugStrategy::Sequential{..} => return Ok(()),
+            SlugStrategy::Obfuscated{salt, alphabet, min_length} => {
+                if salt.is_empty(){
+                    return Err("The `salt` of the slugs can't be empty".to_string());
+                }
+                (alphabet, *min_length, MAX_MIN_LENGTH)
+            },
+            SlugStrategy::Random{length, alphabet} => (alphabet, *length, MAX_LENGTH),
+        };
+        if !(2..=max).contains(&length){
+            return Err(format!("The length of the slugs must be between 2 and {}", max));
+        }
+        if !alphabet.chars().all(|c| c.is_ascii_alphanumeric() || c == '-'){
+            return Err("The alphabet of the slugs can only have letters, digits and `-`".to_string());
+        }
+        if Alphabet::new(alphabet.as_bytes()).is_err() || alphabet.len() < MIN_ALPHABET{
+            return Err(format!("The alphabet of the slugs needs at least {} different characters",
+                MIN_ALPHABET));
+        }
+        Ok(())
+    }
+
+    /// A new random slug, if that is the strategy.
+    pub fn random(&self) -> Option<String>{
+        match self{
+            SlugStrategy::Random{length, alphabet} => {
+                let alphabet = alphabet.as_bytes();
+                let mut rng = rand::thread_rng();
+                Some((0..*length)
+                    .filter_map(|_| alphabet.choose(&mut rng))
+                    .map(|c| char::from(*c))
+                    .collect())
+            },
+            _ => None,
+        }
+    }
+
+    /// The slug that comes from the id of a new url, if the strategy names
+    /// it so and it isn't the base-36 id.
+    pub fn name(&self, id: i64) -> Result<Option<String>, CodecError>{
+        match self{
+            SlugStrategy::Sequential{base62: true} => Base62.encode(id).map(Some),
+            SlugStrategy::Obfuscated{salt, alphabet, min_length} =>
+                Obfuscator::new(alphabet, salt, *min_length).encode(id).map(Some),
+            _ => Ok(None),
+        }
+    }
+}
+
+/// Reorders the alphabet in a way only the salt can repeat.
+fn shuffle(alphabet: &mut [u8], salt: &[u8]){
+    if salt.is_empty(){
+        return;
+    }
+    let mut sum = 0;
+    for (index, i) in (1..alphabet.len()).rev().enumerate(){
+        let v = index % salt.len();
+        let n = usize::from(salt[v]);
+        sum += n;
+        alphabet.swap(i, (n + v + sum) % i);
+    }
+}
+
+/// Writes the ids with an alphabet shuffled by the salt, and then again by
+/// the first character, so the slugs of consecutive ids look unrelated.
+/// The ids are shifted to have at least `min_length` characters.
+pub struct Obfuscator{
+    alphabet: Vec<u8>,
+    salt: Vec<u8>,
+    min_length: usize,
+}
+
+impl Obfuscator{
+    pub fn new(alphabet: &str, salt: &str, min_length: usize) -> Self{
+        let mut shuffled = alphabet.as_bytes().to_vec();
+        shuffle(&mut shuffled, salt.as_bytes());
+        Self{
+            alphabet: shuffled,
+            salt: salt.as_bytes().to_vec(),
+            min_length,
+        }
+    }
+
+    fn shift(&self) -> Result<i64, CodecError>{
+        (self.alphabet.len() as i64)
+            .checked_pow(self.min_length.saturating_sub(2) as u32)
+            .ok_or(CodecError::Overflow)
+    }
+
+    /// The alphabet of the digits that follow the first character.
+    fn digits(&self, lottery: u8) -> Result<Alphabet, CodecError>{
+        let mut alphabet = self.alphabet.clone();
+        let key: Vec<u8> = std::iter::once(lottery).chain(self.salt.iter().copied()).collect();
+        shuffle(&mut alphabet, &key);
+        Alphabet::new(&alphabet)
+    }
+}
+
+impl SlugCodec for Obfuscator{
+    fn encode(&self, id: i64) -> Result<String, CodecError>{
+        if id < 0{
+            return Err(CodecError::Negative(id));
+        }
+        let value = self.shift()?.checked_add(id).ok_or(CodecError::Overflow)?;
+        let lottery = self.alphabet[(value % self.alphabet.len() as i64) as usize];
+        let digits = self.digits(lottery)?.encode(value)?;
+        Ok(format!("{}{}", char::from(lottery), digits))
+    }
+
+    /// Reads the id back, as long as the slug is the one it would give.
+    fn decode(&self, slug: &str) -> Result<i64, CodecError>{
+        let first = slug.chars().next().ok_or(CodecError::Empty)?;
+        let lottery = u8::try_from(first).ok()
+            .filter(|lottery| self.alphabet.contains(lottery))
+            .ok_or(CodecError::InvalidChar(first))?;
+        let rest = &slug[first.len_utf8()..];
+        let id = self.digits(lottery)?.decode(rest)? - self.shift()?;
+        if id < 0 || self.encode(id).as_deref() != Ok(slug){
+            return Err(CodecError::InvalidChar(first));
+        }
+        Ok(id)
+    }
+}
+
+#[test]
+fn obfuscates(){
+    let strategy = SlugStrategy::Obfuscated{
+        salt: "secret".to_string(),
+        alphabet: default_alphabet(),
+        min_length: 6,
+    };
+    let slugs: Vec<String> = (1..1000).filter_map(|id| strategy.name(id).unwrap()).collect();
+    assert!(slugs.iter().all(|slug| slug.len() >= 6));
+    let mut unique = slugs.clone();
+    unique.sort();
+    unique.dedup();
+    assert!(unique.len() == slugs.len());
+    assert!(slugs.len() == 999);
+    assert!(strategy.name(1) == strategy.name(1));
+    assert!(strategy.name(i64::MAX) == Err(CodecError::Overflow));
+    let other = SlugStrategy::Obfuscated{
+        salt: "other".to_string(),
+        alphabet: default_alphabet(),
+        min_length: 6,
+    };
+    assert!(other.name(1) != strategy.name(1));
+    assert!(SlugStrategy::default().name(1) == Ok(None));
+    assert!(SlugStrategy::Sequential{ base62: true }.name(61) == Ok(Some("Z".to_string())));
+}
+
+#[test]
+fn obfuscator_round_trips(){
+    let obfuscator = Obfuscator::new(&default_alphabet(), "secret", 6);
+    for id in [0, 1, 2, 61, 62, 1234, i64::from(u32::MAX) + 1, i64::MAX - obfuscator.shift().unwrap()]{
+        assert_eq!(obfuscator.decode(&obfuscator.encode(id).unwrap()), Ok(id));
+    }
+    let slug = obfuscator.encode(1234).unwrap();
+    assert!(Obfuscator::new(&default_alphabet(), "other", 6).decode(&slug) != Ok(1234));
+    let mut changed = slug.clone();
+    changed.replace_range(..1, if slug.starts_with('a') { "b" } else { "a" });
+    assert!(obfuscator.decode(&changed).is_err());
+    assert_eq!(obfuscator.decode(""), Err(CodecError::Empty));
+    assert_eq!(obfuscator.decode("a.b"), Err(CodecError::InvalidChar('.')));
+    assert_eq!(obfuscator.decode("\u{1F600}"), Err(CodecError::InvalidChar('\u{1F600}')));
+}
+
+#[test]
+fn validates(){
+    let random = SlugStrategy::Random{ length: 8, alphabet: "0123456789abcdef".to_string() };
+    assert!(random.validate().is_ok());
+    let slug = random.random().unwrap();
+    assert!(slug.len() == 8 && slug.chars().all(|c| c.is_ascii_hexdigit()));
+    assert!(SlugStrategy::Random{ length: 8, alphabet: "abc".to_string() }.validate().is_err());
+    assert!(SlugStrategy::Random{ length: 8, alphabet: "aabcdefghijklmnop".to_string() }
+        .validate().is_err());
+    assert!(SlugStrategy::Random{ length: 1, alphabet: default_alphabet() }.validate().is_err());
+    let unsalted = SlugStrategy::Obfuscated{
+        salt: String::new(),
+        alphabet: default_alphabet(),
+        min_length: 6,
+    };
+    assert!(unsalted.validate().is_err());
+}
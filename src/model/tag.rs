@@ -0,0 +1,98 @@
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+use sqlx::{sqlite::{SqliteConnection, SqlitePool, SqliteRow}, query, Row};
+use tracing::{debug, info};
+
+
+/// The tags of a url, in lower case, sorted and without repeats. They are
+/// written as a single string separated by commas, so they fit in a
+/// column of the csv export, and read from that or from a list.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Tags(Vec<String>);
+
+impl Tags{
+    pub fn new<I, S>(tags: I) -> Self
+    where I: IntoIterator<Item = S>, S: AsRef<str>{
+        let mut tags: Vec<String> = tags.into_iter()
+            .map(|tag| tag.as_ref().trim().to_lowercase())
+            .filter(|tag| !tag.is_empty())
+            .collect();
+        tags.sort();
+        tags.dedup();
+        Self(tags)
+    }
+
+    pub fn parse(value: &str) -> Self{
+        Self::new(value.split(','))
+    }
+
+    pub fn is_empty(&self) -> bool{
+        self.0.is_empty()
+    }
+
+    /// Replaces the tags of a url, dropping the ones no url uses anymore.
+    pub async fn save(conn: &mut SqliteConnection, url_id: i64, tags: &Self) -> Result<(), sqlx::Error>{
+        info!("Tags save");
+        let sql = "DELETE FROM url_tags WHERE url_id = $1";
+        debug!("Query: {}", sql);
+        query(sql).bind(url_id).execute(&mut *conn).await?;
+        for name in &tags.0{
+            let sql = "INSERT OR IGNORE INTO tags (name) VALUES($1)";
+            debug!("Query: {}", sql);
+            query(sql).bind(name).execute(&mut *conn).await?;
+            let sql = "INSERT INTO url_tags (url_id, tag_id)
+                       SELECT $1, id FROM tags WHERE name = $2";
+            debug!("Query: {}", sql);
+            query(sql).bind(url_id).bind(name).execute(&mut *conn).await?;
+        }
+        let sql = "DELETE FROM tags WHERE id NOT IN (SELECT tag_id FROM url_tags)";
+        debug!("Query: {}", sql);
+        query(sql).execute(&mut *conn).await?;
+        Ok(())
+    }
+
+    /// Every tag in use and how many urls have it.
+    pub async fn read_all(pool: &SqlitePool) -> Result<Vec<TagCount>, sqlx::Error>{
+        info!("Tags read_all");
+        let sql = "SELECT tags.name, count(*) AS links FROM tags
+                   JOIN url_tags ON url_tags.tag_id = tags.id
+                   GROUP BY tags.name ORDER BY tags.name";
+        debug!("Query: {}", sql);
+        query(sql)
+            .map(|row: SqliteRow| TagCount{ name: row.get("name"), links: row.get("links") })
+            .fetch_all(pool)
+            .await
+    }
+}
+
+#[derive(Debug, Serialize, Clone, PartialEq, Eq)]
+pub struct TagCount{
+    pub name: String,
+    pub links: i64,
+}
+
+impl Serialize for Tags{
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error>{
+        serializer.serialize_str(&self.0.join(","))
+    }
+}
+
+impl<'de> Deserialize<'de> for Tags{
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error>{
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Value{
+            Joined(String),
+            List(Vec<String>),
+        }
+        match Option::<Value>::deserialize(deserializer)?{
+            None => Ok(Self::default()),
+            Some(Value::Joined(value)) => Ok(Self::parse(&value)),
+            Some(Value::List(tags)) => {
+                if tags.iter().any(|tag| tag.contains(',')){
+                    return Err(de::Error::custom("A tag can't have a comma"));
+                }
+                Ok(Self::new(tags))
+            },
+        }
+    }
+}
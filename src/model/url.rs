@@ -1,17 +1,69 @@
+use std::str::FromStr;
+
 use serde::{Serialize, Deserialize};
-use sqlx::{sqlite::{SqlitePool, SqliteRow}, query, Row};
+use sqlx::{sqlite::{Sqlite, SqliteConnection, SqlitePool, SqliteRow}, query, Executor, Row};
+use futures::stream::BoxStream;
+use url::form_urlencoded;
 use chrono::{DateTime, Utc};
 use tracing::{debug, info};
 
-use super::radix::to_d36;
+use super::{
+    radix::{Base36, SlugCodec},
+    parameters::double_option,
+    tag::Tags,
+    domain::Domain,
+    slug::{SlugStrategy, MAX_ATTEMPTS},
+};
+
+const MAX_SLUG_LENGTH: usize = 64;
+const RESERVED_SLUGS: &[&str] = &["api", "assets"];
+
+/// The columns of a url, with its tags joined as in `Tags` and the host of
+/// its domain, to be used in place of `*` in the queries of urls.
+macro_rules! columns{
+    () => {
+        "*, (SELECT group_concat(tags.name, ',') FROM url_tags
+         JOIN tags ON tags.id = url_tags.tag_id
+         WHERE url_tags.url_id = urls.id) AS tags,
+         (SELECT host FROM domains WHERE domains.id = urls.domain_id) AS domain"
+    };
+}
+
+/// The ids of the urls with the tag bound as the parameter given.
+macro_rules! tagged{
+    ($tag:literal) => {
+        concat!("SELECT url_tags.url_id FROM url_tags
+                 JOIN tags ON tags.id = url_tags.tag_id WHERE tags.name = ", $tag)
+    };
+}
 
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Url{
     id: i64,
     src: String,
+    slug: Option<String>,
+    /// The slug the strategy gave the url when it had no custom one, if it
+    /// isn't its base-36 id.
+    code: Option<String>,
     num: u32,
     active: bool,
+    expires_at: Option<DateTime<Utc>>,
+    fallback_url: Option<String>,
+    #[serde(skip_serializing)]
+    password: Option<String>,
+    redirect_type: Option<RedirectType>,
+    forward_query: bool,
+    forward_path: bool,
+    utm: Utm,
+    utm_on_redirect: bool,
+    sticky_variant: bool,
+    title: Option<String>,
+    notes: Option<String>,
+    tags: Tags,
+    /// The domain of the url, that is the default one when it is `None`.
+    domain_id: Option<i64>,
+    domain: Option<String>,
     created_at: DateTime<Utc>,
     updated_at: DateTime<Utc>,
 }
@@ -21,9 +73,412 @@ pub struct ShortUrl{
     src: String,
     short: String,
     num: u32,
+    active: bool,
+    expires_at: Option<DateTime<Utc>>,
+    fallback_url: Option<String>,
+    protected: bool,
+    /// The hash of the password, only in exports, where it is empty for the
+    /// urls that aren't protected so every row has the column.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    password: Option<String>,
+    redirect_type: Option<RedirectType>,
+    forward_query: bool,
+    forward_path: bool,
+    utm_source: Option<String>,
+    utm_medium: Option<String>,
+    utm_campaign: Option<String>,
+    utm_term: Option<String>,
+    utm_content: Option<String>,
+    utm_on_redirect: bool,
+    sticky_variant: bool,
+    title: Option<String>,
+    notes: Option<String>,
+    tags: Tags,
+    domain: Option<String>,
+    created_at: DateTime<Utc>,
+    updated_at: DateTime<Utc>,
+}
+
+/// The UTM parameters that tag a url for a campaign.
+#[derive(Debug, Default, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct Utm{
+    #[serde(default, rename = "utm_source")]
+    pub source: Option<String>,
+    #[serde(default, rename = "utm_medium")]
+    pub medium: Option<String>,
+    #[serde(default, rename = "utm_campaign")]
+    pub campaign: Option<String>,
+    #[serde(default, rename = "utm_term")]
+    pub term: Option<String>,
+    #[serde(default, rename = "utm_content")]
+    pub content: Option<String>,
+}
+
+impl Utm{
+    /// Trims every parameter, leaving out the empty ones.
+    pub fn clean(self) -> Self{
+        let clean = |value: Option<String>| value
+            .map(|value| value.trim().to_string())
+            .filter(|value| !value.is_empty());
+        Self{
+            source: clean(self.source),
+            medium: clean(self.medium),
+            campaign: clean(self.campaign),
+            term: clean(self.term),
+            content: clean(self.content),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool{
+        self.pairs().is_empty()
+    }
+
+    fn pairs(&self) -> Vec<(&'static str, &str)>{
+        vec![
+            ("utm_source", &self.source),
+            ("utm_medium", &self.medium),
+            ("utm_campaign", &self.campaign),
+            ("utm_term", &self.term),
+            ("utm_content", &self.content),
+        ]
+        .into_iter()
+        .filter_map(|(key, value)| value.as_deref().map(|value| (key, value)))
+        .collect()
+    }
+
+    fn to_query(&self) -> String{
+        form_urlencoded::Serializer::new(String::new())
+            .extend_pairs(self.pairs())
+            .finish()
+    }
+
+    /// Adds the parameters to `src`, but not the ones it already has.
+    pub fn tag(&self, src: &str) -> String{
+        match ::url::Url::parse(src){
+            Ok(mut destination) if !self.is_empty() => {
+                merge_query(&mut destination, &self.to_query());
+                destination.into()
+            },
+            _ => src.to_string(),
+        }
+    }
+}
+
+/// Adds the parameters of `query` to the ones of `destination`, leaving out
+/// the ones it already has.
+fn merge_query(destination: &mut ::url::Url, query: &str){
+    let current = destination.query().unwrap_or_default().to_string();
+    let taken: Vec<String> = form_urlencoded::parse(current.as_bytes())
+        .map(|(key, _)| key.into_owned())
+        .collect();
+    let pairs = current.split('&')
+        .chain(query.split('&').filter(|pair| {
+            form_urlencoded::parse(pair.as_bytes())
+                .next()
+                .map_or(false, |(key, _)| !taken.iter().any(|taken| *taken == key))
+        }))
+        .filter(|pair| !pair.is_empty())
+        .collect::<Vec<&str>>();
+    destination.set_query(Some(&pairs.join("&")));
+}
+
+/// The urls tagged with a campaign.
+#[derive(Serialize)]
+pub struct Campaign{
+    pub name: String,
+    pub clicks: u64,
+    pub links: Vec<ShortUrl>,
+}
+
+/// Everything but the destination that can be chosen for a new url.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct UrlOptions{
+    pub slug: Option<String>,
+    pub expires_at: Option<DateTime<Utc>>,
+    pub fallback_url: Option<String>,
+    /// Comes in clear from the request, but must be hashed before the url
+    /// is created.
+    pub password: Option<String>,
+    pub redirect_type: Option<RedirectType>,
+    /// Appends the query of every visit to the one of the destination.
+    #[serde(default)]
+    pub forward_query: bool,
+    /// Appends the path after the short one to the destination, so the url
+    /// works as a prefix.
+    #[serde(default)]
+    pub forward_path: bool,
+    #[serde(flatten)]
+    pub utm: Utm,
+    /// Keeps the UTM parameters out of the destination and adds them on
+    /// every visit instead.
+    #[serde(default)]
+    pub utm_on_redirect: bool,
+    /// Keeps every visitor on the variant they got the first time, with a
+    /// cookie.
+    #[serde(default)]
+    pub sticky_variant: bool,
+    pub title: Option<String>,
+    pub notes: Option<String>,
+    #[serde(default)]
+    pub tags: Tags,
+    /// The host of the domain of the url, that has to be looked up as
+    /// `domain_id` before the url is created.
+    pub domain: Option<String>,
+    #[serde(skip)]
+    pub domain_id: Option<i64>,
+    /// How to name the url when it has no custom slug.
+    #[serde(skip)]
+    pub strategy: SlugStrategy,
+}
+
+impl UrlOptions{
+    pub fn is_empty(&self) -> bool{
+        self.slug.is_none() && self.expires_at.is_none() && self.fallback_url.is_none()
+            && self.password.is_none() && self.redirect_type.is_none()
+            && !self.forward_query && !self.forward_path
+            && self.utm.is_empty() && !self.utm_on_redirect && !self.sticky_variant
+            && self.title.is_none() && self.notes.is_none() && self.tags.is_empty()
+    }
+}
+
+/// How a visit is sent to the destination: with one of the redirect status
+/// codes or with a page that leaves by itself, through a meta refresh or
+/// after a moment with javascript.
+#[derive(Debug, Default, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub enum RedirectType{
+    #[serde(rename = "301")]
+    MovedPermanently,
+    #[default]
+    #[serde(rename = "302")]
+    Found,
+    #[serde(rename = "307")]
+    TemporaryRedirect,
+    #[serde(rename = "308")]
+    PermanentRedirect,
+    #[serde(rename = "refresh")]
+    Refresh,
+    #[serde(rename = "interstitial")]
+    Interstitial,
+}
+
+impl RedirectType{
+    fn as_str(&self) -> &'static str{
+        match self{
+            RedirectType::MovedPermanently => "301",
+            RedirectType::Found => "302",
+            RedirectType::TemporaryRedirect => "307",
+            RedirectType::PermanentRedirect => "308",
+            RedirectType::Refresh => "refresh",
+            RedirectType::Interstitial => "interstitial",
+        }
+    }
+}
+
+impl FromStr for RedirectType{
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err>{
+        match value{
+            "301" => Ok(RedirectType::MovedPermanently),
+            "302" => Ok(RedirectType::Found),
+            "307" => Ok(RedirectType::TemporaryRedirect),
+            "308" => Ok(RedirectType::PermanentRedirect),
+            "refresh" => Ok(RedirectType::Refresh),
+            "interstitial" => Ok(RedirectType::Interstitial),
+            _ => Err(format!("Unknown redirect type '{}'", value)),
+        }
+    }
+}
+
+#[derive(Debug, Default, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Sort{
+    #[default]
+    Created,
+    Src,
+    Slug,
+    Clicks,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Order{
+    Asc,
+    #[default]
+    Desc,
+}
+
+impl FromStr for Sort{
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err>{
+        match value{
+            "created" => Ok(Sort::Created),
+            "src" => Ok(Sort::Src),
+            "slug" => Ok(Sort::Slug),
+            "clicks" => Ok(Sort::Clicks),
+            _ => Err(format!("Can't sort by '{}'", value)),
+        }
+    }
+}
+
+impl FromStr for Order{
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err>{
+        match value{
+            "asc" => Ok(Order::Asc),
+            "desc" => Ok(Order::Desc),
+            _ => Err(format!("Unknown order '{}'", value)),
+        }
+    }
+}
+
+impl Sort{
+    fn column(&self) -> &'static str{
+        match self{
+            Sort::Created => "created_at",
+            Sort::Src => "src",
+            Sort::Slug => "slug",
+            Sort::Clicks => "num",
+        }
+    }
+}
+
+impl Order{
+    fn sql(&self) -> &'static str{
+        match self{
+            Order::Asc => "ASC",
+            Order::Desc => "DESC",
+        }
+    }
+}
+
+/// Filter and order for a listing of urls.
+#[derive(Debug, Default, Clone)]
+pub struct Search{
+    pub query: Option<String>,
+    pub tag: Option<String>,
+    /// The domain of the urls, `Some(None)` being the default one, or
+    /// every domain when it is `None`.
+    pub domain: Option<Option<i64>>,
+    pub sort: Sort,
+    pub order: Order,
+}
+
+impl Search{
+    /// `LIKE` pattern for the query, with its wildcards escaped.
+    fn pattern(&self) -> Option<String>{
+        self.query.as_ref().map(|query| {
+            let escaped = query
+                .replace('\\', "\\\\")
+                .replace('%', "\\%")
+                .replace('_', "\\_");
+            format!("%{}%", escaped)
+        })
+    }
+}
+
+/// Changes to an existing url. A field set to `null` is cleared, a missing
+/// one is kept as it is.
+#[derive(Debug, Default, Deserialize)]
+pub struct UrlPatch{
+    pub src: Option<String>,
+    pub active: Option<bool>,
+    #[serde(default, deserialize_with = "double_option")]
+    pub expires_at: Option<Option<DateTime<Utc>>>,
+    #[serde(default, deserialize_with = "double_option")]
+    pub fallback_url: Option<Option<String>>,
+    /// As in `UrlOptions`, it has to be hashed before being applied.
+    #[serde(default, deserialize_with = "double_option")]
+    pub password: Option<Option<String>>,
+    /// Cleared, it goes back to the default of the configuration.
+    #[serde(default, deserialize_with = "double_option")]
+    pub redirect_type: Option<Option<RedirectType>>,
+    pub forward_query: Option<bool>,
+    pub forward_path: Option<bool>,
+    pub sticky_variant: Option<bool>,
+    #[serde(default, deserialize_with = "double_option")]
+    pub title: Option<Option<String>>,
+    #[serde(default, deserialize_with = "double_option")]
+    pub notes: Option<Option<String>>,
+    pub tags: Option<Tags>,
+}
+
+/// A row to import, in the same shape as an exported `ShortUrl`. The short
+/// path is kept as the slug, so links moved from elsewhere keep working.
+#[derive(Debug, Deserialize)]
+pub struct ImportRow{
+    pub src: String,
+    #[serde(default, alias = "slug")]
+    pub short: Option<String>,
+    #[serde(default)]
+    pub num: u32,
+    #[serde(default = "default_active")]
+    pub active: bool,
+    #[serde(default)]
+    pub expires_at: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub fallback_url: Option<String>,
+    #[serde(default)]
+    pub protected: bool,
+    /// The hash of the password, as exported.
+    #[serde(default)]
+    pub password: Option<String>,
+    #[serde(default)]
+    pub redirect_type: Option<RedirectType>,
+    #[serde(default)]
+    pub forward_query: bool,
+    #[serde(default)]
+    pub forward_path: bool,
+    #[serde(default)]
+    pub utm_source: Option<String>,
+    #[serde(default)]
+    pub utm_medium: Option<String>,
+    #[serde(default)]
+    pub utm_campaign: Option<String>,
+    #[serde(default)]
+    pub utm_term: Option<String>,
+    #[serde(default)]
+    pub utm_content: Option<String>,
+    #[serde(default)]
+    pub utm_on_redirect: bool,
+    #[serde(default)]
+    pub sticky_variant: bool,
+    #[serde(default)]
+    pub title: Option<String>,
+    #[serde(default)]
+    pub notes: Option<String>,
+    #[serde(default)]
+    pub tags: Tags,
+    #[serde(default)]
+    pub domain: Option<String>,
+}
+
+fn default_active() -> bool{
+    true
+}
+
+#[derive(Debug, Serialize, PartialEq, Eq)]
+#[serde(tag = "status", rename_all = "lowercase")]
+pub enum ImportStatus{
+    Created{short: String},
+    Duplicate{short: String},
+    Invalid{error: String},
+}
+
+#[derive(Debug, Serialize)]
+pub struct ImportResult{
+    pub row: usize,
+    pub src: Option<String>,
+    #[serde(flatten)]
+    pub status: ImportStatus,
 }
 
 impl Url{
+    pub fn get_id(&self) -> i64{
+        self.id
+    }
     pub fn get_src(&self) -> &str{
         &self.src
     }
@@ -32,152 +487,748 @@ impl Url{
             src: self.src.to_string(),
             short: self.get_url(),
             num: self.num,
+            active: self.active,
+            expires_at: self.expires_at,
+            fallback_url: self.fallback_url.clone(),
+            protected: self.is_protected(),
+            password: None,
+            redirect_type: self.redirect_type,
+            forward_query: self.forward_query,
+            forward_path: self.forward_path,
+            utm_source: self.utm.source.clone(),
+            utm_medium: self.utm.medium.clone(),
+            utm_campaign: self.utm.campaign.clone(),
+            utm_term: self.utm.term.clone(),
+            utm_content: self.utm.content.clone(),
+            utm_on_redirect: self.utm_on_redirect,
+            sticky_variant: self.sticky_variant,
+            title: self.title.clone(),
+            notes: self.notes.clone(),
+            tags: self.tags.clone(),
+            domain: self.domain.clone(),
+            created_at: self.created_at,
+            updated_at: self.updated_at,
+        }
+    }
+    /// The url as exported, with the hash of its password so it is still
+    /// protected when imported.
+    pub fn get_export(&self) -> ShortUrl{
+        ShortUrl{
+            password: Some(self.password.clone().unwrap_or_default()),
+            ..self.get_short()
         }
     }
     pub fn get_num(&self) -> u32{
         self.num
     }
+    pub fn get_created_at(&self) -> DateTime<Utc>{
+        self.created_at
+    }
+    pub fn get_slug(&self) -> Option<&str>{
+        self.slug.as_deref()
+    }
+    pub fn get_domain(&self) -> Option<&str>{
+        self.domain.as_deref()
+    }
+    pub fn get_fallback_url(&self) -> Option<&str>{
+        self.fallback_url.as_deref()
+    }
+    pub fn apply(&mut self, patch: UrlPatch){
+        if let Some(src) = patch.src{
+            self.src = src;
+        }
+        if let Some(active) = patch.active{
+            self.active = active;
+        }
+        if let Some(expires_at) = patch.expires_at{
+            self.expires_at = expires_at;
+        }
+        if let Some(fallback_url) = patch.fallback_url{
+            self.fallback_url = fallback_url;
+        }
+        if let Some(password) = patch.password{
+            self.password = password;
+        }
+        if let Some(redirect_type) = patch.redirect_type{
+            self.redirect_type = redirect_type;
+        }
+        if let Some(forward_query) = patch.forward_query{
+            self.forward_query = forward_query;
+        }
+        if let Some(forward_path) = patch.forward_path{
+            self.forward_path = forward_path;
+        }
+        if let Some(sticky_variant) = patch.sticky_variant{
+            self.sticky_variant = sticky_variant;
+        }
+        if let Some(title) = patch.title{
+            self.title = title;
+        }
+        if let Some(notes) = patch.notes{
+            self.notes = notes;
+        }
+        if let Some(tags) = patch.tags{
+            self.tags = tags;
+        }
+    }
+    /// The hash of the password that protects the url, if any.
+    pub fn get_password(&self) -> Option<&str>{
+        self.password.as_deref()
+    }
+    /// The redirect type chosen for the url, if it isn't the default one.
+    pub fn get_redirect_type(&self) -> Option<RedirectType>{
+        self.redirect_type
+    }
+    /// Where a visit goes: the destination, or the one chosen for the
+    /// visitor by a rule or a variant, plus its UTM parameters when they are
+    /// added on every visit, and the path after the short one and the query
+    /// of the request when the url forwards them. The parameters of the
+    /// destination win over the UTM ones, and both over the ones of the
+    /// request with the same name.
+    pub fn get_destination(&self, chosen: Option<&str>, path: Option<&str>, query: Option<&str>) -> String{
+        let src = chosen.unwrap_or(&self.src);
+        let path = path.filter(|path| self.forward_path && !path.is_empty());
+        let query = query.filter(|query| self.forward_query && !query.is_empty());
+        let tags = Some(self.utm.to_query()).filter(|tags| self.utm_on_redirect && !tags.is_empty());
+        if path.is_none() && query.is_none() && tags.is_none(){
+            return src.to_string();
+        }
+        let mut destination = match ::url::Url::parse(src){
+            Ok(destination) => destination,
+            Err(_) => return src.to_string(),
+        };
+        if let Some(path) = path{
+            if let Ok(mut segments) = destination.path_segments_mut(){
+                segments.pop_if_empty().extend(path.split('/'));
+            }
+        }
+        for query in tags.iter().map(String::as_str).chain(query){
+            merge_query(&mut destination, query);
+        }
+        destination.into()
+    }
+    pub fn is_forwarding_path(&self) -> bool{
+        self.forward_path
+    }
+    pub fn is_sticky_variant(&self) -> bool{
+        self.sticky_variant
+    }
+    pub fn is_protected(&self) -> bool{
+        self.password.is_some()
+    }
+    pub fn is_active(&self) -> bool{
+        self.active
+    }
+    pub fn is_expired(&self) -> bool{
+        matches!(self.expires_at, Some(expires_at) if expires_at <= Utc::now())
+    }
 
     pub fn get_url(&self) -> String{
-        let value: u32 = self.id.try_into().unwrap();
-        to_d36(value)
+        match self.slug.as_ref().or(self.code.as_ref()){
+            Some(slug) => slug.to_string(),
+            // The ids sqlite gives are never negative
+            None => Base36.encode(self.id).unwrap_or_default(),
+        }
+    }
+
+    /// A custom slug can only use letters, digits, `-` and `_`, and can't
+    /// start with `_`, which is kept for the internal routes.
+    pub fn is_valid_slug(slug: &str) -> bool{
+        !slug.is_empty()
+            && slug.len() <= MAX_SLUG_LENGTH
+            && !slug.starts_with('_')
+            && !RESERVED_SLUGS.contains(&slug)
+            && slug.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
     }
 
     fn from_row(row: SqliteRow) -> Self{
         Self{
             id: row.get("id"),
             src: row.get("src"),
+            slug: row.get("slug"),
+            code: row.get("code"),
             num: row.get("num"),
             active: row.get("active"),
+            expires_at: row.get("expires_at"),
+            fallback_url: row.get("fallback_url"),
+            password: row.get("password"),
+            redirect_type: row.get::<Option<String>, _>("redirect_type")
+                .and_then(|value| value.parse().ok()),
+            forward_query: row.get("forward_query"),
+            forward_path: row.get("forward_path"),
+            utm: Utm{
+                source: row.get("utm_source"),
+                medium: row.get("utm_medium"),
+                campaign: row.get("utm_campaign"),
+                term: row.get("utm_term"),
+                content: row.get("utm_content"),
+            },
+            utm_on_redirect: row.get("utm_on_redirect"),
+            sticky_variant: row.get("sticky_variant"),
+            title: row.get("title"),
+            notes: row.get("notes"),
+            tags: row.get::<Option<String>, _>("tags")
+                .map(|tags| Tags::parse(&tags))
+                .unwrap_or_default(),
+            domain_id: row.get("domain_id"),
+            domain: row.get("domain"),
             created_at: row.get("created_at"),
             updated_at: row.get("updated_at"),
         }
     }
 
-    pub async fn create(pool: &SqlitePool, src: &str) -> Result<Self, sqlx::Error>{
+    pub async fn create(pool: &SqlitePool, src: &str, options: &UrlOptions) -> Result<Self, sqlx::Error>{
         info!("Url create");
-        let num = 0;
-        let active = true;
+        let mut conn = pool.acquire().await?;
+        Self::insert(&mut conn, src, options, 0, true).await
+    }
+
+    async fn insert(conn: &mut SqliteConnection, src: &str, options: &UrlOptions,
+            num: u32, active: bool) -> Result<Self, sqlx::Error>{
         let created_at = Utc::now();
-        let updated_at = created_at.clone();
-        let sql = "INSERT OR IGNORE INTO urls (src, num, active, created_at, updated_at)
-                   VALUES($1, $2, $3, $4, $4) RETURNING *";
+        let updated_at = created_at;
+        let sql = concat!("INSERT OR IGNORE INTO urls (src, slug, num, active, expires_at,
+                   fallback_url, password, redirect_type, forward_query, forward_path,
+                   utm_source, utm_medium, utm_campaign, utm_term, utm_content,
+                   utm_on_redirect, sticky_variant, title, notes, domain_id, created_at,
+                   updated_at, code)
+                   VALUES($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14,
+                   $15, $16, $17, $18, $19, $20, $21, $22, $23) RETURNING ", columns!());
+        debug!("Query: {}", sql);
+        // Every name that turns out to be taken, drawn at random, given by
+        // the id or lost to a concurrent insert, counts as an attempt
+        for _ in 0..MAX_ATTEMPTS{
+            let code = match options.strategy.random().filter(|_| options.slug.is_none()){
+                Some(code) if !Self::is_valid_slug(&code)
+                        || Self::is_short_taken(&mut *conn, options.domain_id, &code).await => {
+                    continue;
+                },
+                code => code,
+            };
+            let url = query(sql)
+                .bind(src)
+                .bind(&options.slug)
+                .bind(num)
+                .bind(active)
+                .bind(options.expires_at)
+                .bind(&options.fallback_url)
+                .bind(&options.password)
+                .bind(options.redirect_type.as_ref().map(RedirectType::as_str))
+                .bind(options.forward_query)
+                .bind(options.forward_path)
+                .bind(&options.utm.source)
+                .bind(&options.utm.medium)
+                .bind(&options.utm.campaign)
+                .bind(&options.utm.term)
+                .bind(&options.utm.content)
+                .bind(options.utm_on_redirect)
+                .bind(options.sticky_variant)
+                .bind(&options.title)
+                .bind(&options.notes)
+                .bind(options.domain_id)
+                .bind(created_at)
+                .bind(updated_at)
+                .bind(&code)
+                .map(Self::from_row)
+                .fetch_one(&mut *conn)
+                .await;
+            let url = match url{
+                Ok(url) => url,
+                // The insert was ignored, because another one took the code
+                // in the meantime or for a reason a new code won't solve
+                Err(sqlx::Error::RowNotFound) => match &code{
+                    Some(code) if Self::is_short_taken(&mut *conn, options.domain_id, code).await => {
+                        debug!("The code {} was taken meanwhile, retrying", code);
+                        continue;
+                    },
+                    _ => return Err(sqlx::Error::RowNotFound),
+                },
+                Err(e) => return Err(e),
+            };
+            // A slug that comes from the id can only be set now. If it is
+            // taken the next id will give another one.
+            let name = options.strategy.name(url.id)
+                .map_err(|e| sqlx::Error::Configuration(Box::new(e)))?;
+            let url = match name.filter(|_| url.slug.is_none()){
+                Some(code) if Self::is_valid_slug(&code)
+                        && !Self::is_short_taken_except(&mut *conn, url.domain_id, &code, Some(url.id)).await => {
+                    match Self::set_code(&mut *conn, url.id, &code).await{
+                        Ok(url) => url,
+                        // Another insert took the name in the meantime
+                        Err(sqlx::Error::RowNotFound) => {
+                            debug!("The name of id {} was taken meanwhile, retrying", url.id);
+                            Self::delete(&mut *conn, url.id).await?;
+                            continue;
+                        },
+                        Err(e) => return Err(e),
+                    }
+                },
+                Some(_) => {
+                    debug!("The name of id {} is taken, retrying", url.id);
+                    Self::delete(&mut *conn, url.id).await?;
+                    continue;
+                },
+                None => url,
+            };
+            // A custom slug could already be using the base-36 id of this
+            // row. It would hide the new link, so take the next id instead.
+            if url.slug.is_some() || url.code.is_some()
+                    || !Self::exists_slug(&mut *conn, url.domain_id, &url.get_url()).await{
+                Tags::save(&mut *conn, url.id, &options.tags).await?;
+                return Ok(Self{tags: options.tags.clone(), ..url});
+            }
+            debug!("Id {} is used as slug, retrying", url.get_url());
+            Self::delete(&mut *conn, url.id).await?;
+        }
+        Err(sqlx::Error::Configuration(format!(
+            "Can't find a free slug after {} attempts", MAX_ATTEMPTS).into()))
+    }
+    /// Streams every url, or the ones with `tag`, oldest first, without
+    /// loading them all at once.
+    pub fn read_all<'a>(pool: &'a SqlitePool, tag: Option<&'a str>) -> BoxStream<'a, Result<Self, sqlx::Error>>{
+        info!("Url read_all");
+        let sql = concat!("SELECT ", columns!(), " FROM urls
+                   WHERE $1 IS NULL OR id IN (", tagged!("$1"), ")
+                   ORDER BY id");
+        debug!("Query: {}", sql);
+        query(sql)
+            .bind(tag)
+            .map(Self::from_row)
+            .fetch(pool)
+    }
+
+    /// Creates the urls of an import in a single transaction, reporting
+    /// what happened with every row. Rows that could not be parsed come as
+    /// errors and are reported as invalid. The ones without a short path
+    /// are named by `strategy`.
+    pub async fn import(pool: &SqlitePool, strategy: &SlugStrategy, rows: Vec<Result<ImportRow, String>>)
+            -> Result<Vec<ImportResult>, sqlx::Error>{
+        info!("Url import");
+        let mut tx = pool.begin().await?;
+        let mut results = Vec::with_capacity(rows.len());
+        for (index, row) in rows.into_iter().enumerate(){
+            let (src, status) = match row{
+                Ok(row) => (Some(row.src.clone()), Self::import_row(&mut tx, strategy, row).await?),
+                Err(error) => (None, ImportStatus::Invalid{error}),
+            };
+            results.push(ImportResult{row: index + 1, src, status});
+        }
+        tx.commit().await?;
+        Ok(results)
+    }
+
+    async fn import_row(conn: &mut SqliteConnection, strategy: &SlugStrategy, row: ImportRow)
+            -> Result<ImportStatus, sqlx::Error>{
+        let slug = row.short.filter(|short| !short.is_empty());
+        if row.src.is_empty(){
+            return Ok(ImportStatus::Invalid{
+                error: "The destination can't be empty".to_string(),
+            });
+        }
+        if let Some(slug) = slug.as_deref().filter(|slug| !Self::is_valid_slug(slug)){
+            return Ok(ImportStatus::Invalid{
+                error: format!("The slug '{}' is not valid", slug),
+            });
+        }
+        let password = row.password.filter(|password| !password.is_empty());
+        match &password{
+            Some(password) if !crate::password::is_hash(password) => return Ok(ImportStatus::Invalid{
+                error: "The password must be a hash".to_string(),
+            }),
+            None if row.protected => return Ok(ImportStatus::Invalid{
+                error: "The url is protected but has no password".to_string(),
+            }),
+            _ => {},
+        }
+        let utm = Utm{
+            source: row.utm_source,
+            medium: row.utm_medium,
+            campaign: row.utm_campaign,
+            term: row.utm_term,
+            content: row.utm_content,
+        }.clean();
+        let domain_id = match row.domain.as_deref().filter(|domain| !domain.is_empty()){
+            Some(host) => match Domain::read_from_host(&mut *conn,
+                    &Domain::normalize(host).unwrap_or_default()).await{
+                Ok(domain) => Some(domain.get_id()),
+                Err(sqlx::Error::RowNotFound) => return Ok(ImportStatus::Invalid{
+                    error: format!("The domain '{}' is not served", host),
+                }),
+                Err(e) => return Err(e),
+            },
+            None => None,
+        };
+        let existing = if row.utm_on_redirect{
+            Self::read_from_tagged_url(&mut *conn, domain_id, &row.src, &utm).await
+        }else{
+            Self::read_from_url(&mut *conn, domain_id, &row.src).await
+        };
+        match existing{
+            Ok(url) => return Ok(ImportStatus::Duplicate{short: url.get_url()}),
+            Err(sqlx::Error::RowNotFound) => {},
+            Err(e) => return Err(e),
+        }
+        if let Some(slug) = &slug{
+            if Self::is_short_taken(&mut *conn, domain_id, slug).await{
+                return Ok(ImportStatus::Duplicate{short: slug.to_string()});
+            }
+        }
+        let options = UrlOptions{
+            slug,
+            expires_at: row.expires_at,
+            fallback_url: row.fallback_url.filter(|fallback| !fallback.is_empty()),
+            password,
+            redirect_type: row.redirect_type,
+            forward_query: row.forward_query,
+            forward_path: row.forward_path,
+            utm,
+            utm_on_redirect: row.utm_on_redirect,
+            sticky_variant: row.sticky_variant,
+            title: row.title.filter(|title| !title.is_empty()),
+            notes: row.notes.filter(|notes| !notes.is_empty()),
+            tags: row.tags,
+            domain: None,
+            domain_id,
+            strategy: strategy.clone(),
+        };
+        let url = Self::insert(conn, &row.src, &options, row.num, row.active).await?;
+        Ok(ImportStatus::Created{short: url.get_url()})
+    }
+
+    pub async fn read_from_url<'e, E>(executor: E, domain_id: Option<i64>, src: &str) -> Result<Self, sqlx::Error>
+    where E: Executor<'e, Database = Sqlite>{
+        info!("Url aread_from_url");
+        let sql = concat!("SELECT ", columns!(), " FROM urls WHERE IFNULL(domain_id, 0) = IFNULL($2, 0)
+                   AND src = $1 AND utm_on_redirect = FALSE LIMIT 1");
         debug!("Query: {}", sql);
         query(sql)
             .bind(src)
-            .bind(num)
-            .bind(active)
-            .bind(created_at)
-            .bind(updated_at)
+            .bind(domain_id)
             .map(Self::from_row)
-            .fetch_one(pool)
+            .fetch_one(executor)
             .await
     }
-    pub async fn read_from_url(pool: &SqlitePool, src: &str) -> Result<Self, sqlx::Error>{
-        info!("Url aread_from_url");
-        let sql = "SELECT * FROM urls WHERE src = $1 LIMIT 1";
+
+    /// The url that adds the same UTM parameters to `src` on every visit.
+    /// Many of them can share the destination, one for every campaign.
+    pub async fn read_from_tagged_url<'e, E>(executor: E, domain_id: Option<i64>, src: &str,
+            utm: &Utm) -> Result<Self, sqlx::Error>
+    where E: Executor<'e, Database = Sqlite>{
+        info!("Url read_from_tagged_url");
+        let sql = concat!("SELECT ", columns!(), " FROM urls WHERE src = $1 AND utm_on_redirect = TRUE
+                   AND utm_source IS $2 AND utm_medium IS $3 AND utm_campaign IS $4
+                   AND utm_term IS $5 AND utm_content IS $6 AND domain_id IS $7 LIMIT 1");
         debug!("Query: {}", sql);
         query(sql)
             .bind(src)
+            .bind(&utm.source)
+            .bind(&utm.medium)
+            .bind(&utm.campaign)
+            .bind(&utm.term)
+            .bind(&utm.content)
+            .bind(domain_id)
+            .map(Self::from_row)
+            .fetch_one(executor)
+            .await
+    }
+
+    /// The urls with a campaign, grouped by it.
+    pub async fn read_campaigns(pool: &SqlitePool) -> Result<Vec<Campaign>, sqlx::Error>{
+        info!("Url read_campaigns");
+        let sql = concat!("SELECT ", columns!(), " FROM urls WHERE utm_campaign IS NOT NULL
+                   ORDER BY utm_campaign, id");
+        debug!("Query: {}", sql);
+        let urls = query(sql)
+            .map(Self::from_row)
+            .fetch_all(pool)
+            .await?;
+        let mut campaigns: Vec<Campaign> = Vec::new();
+        for url in urls{
+            let name = url.utm.campaign.clone().unwrap_or_default();
+            match campaigns.last_mut(){
+                Some(campaign) if campaign.name == name => {
+                    campaign.clicks += u64::from(url.num);
+                    campaign.links.push(url.get_short());
+                },
+                _ => campaigns.push(Campaign{
+                    name,
+                    clicks: u64::from(url.num),
+                    links: vec![url.get_short()],
+                }),
+            }
+        }
+        Ok(campaigns)
+    }
+
+    pub async fn read_from_slug(pool: &SqlitePool, domain_id: Option<i64>, slug: &str) -> Result<Self, sqlx::Error>{
+        info!("Url read_from_slug");
+        let sql = concat!("SELECT ", columns!(), " FROM urls WHERE IFNULL(domain_id, 0) = IFNULL($2, 0)
+                   AND (slug = $1 OR code = $1) LIMIT 1");
+        debug!("Query: {}", sql);
+        query(sql)
+            .bind(slug)
+            .bind(domain_id)
             .map(Self::from_row)
             .fetch_one(pool)
             .await
     }
 
-    pub async fn read_or_create(pool: &SqlitePool, src: &str) -> Result<Self, sqlx::Error>{
+    /// Looks for the url behind a short path of a domain, slugs first and
+    /// then the base-36 ids. Only the urls named by their ids can be found
+    /// from them, so the others can't be walked through.
+    pub async fn read_from_short(pool: &SqlitePool, domain_id: Option<i64>, short: &str) -> Result<Self, sqlx::Error>{
+        info!("Url read_from_short");
+        match Self::read_from_slug(pool, domain_id, short).await{
+            Err(sqlx::Error::RowNotFound) => match Base36.decode(short){
+                Ok(id) => Self::read(pool, id).await
+                    .and_then(|url| if url.domain_id == domain_id && url.slug.is_none()
+                            && url.code.is_none(){
+                        Ok(url)
+                    }else{
+                        Err(sqlx::Error::RowNotFound)
+                    }),
+                Err(e) => {
+                    debug!("{} is not an id. {}", short, e);
+                    Err(sqlx::Error::RowNotFound)
+                },
+            },
+            result => result,
+        }
+    }
+
+    /// The url of `src`, or a new one with `options` if there is none.
+    pub async fn read_or_create(pool: &SqlitePool, src: &str, options: &UrlOptions) -> Result<Self, sqlx::Error>{
         info!("Url read_or_create");
-        match Self::read_from_url(pool, src).await{
+        match Self::read_from_url(pool, options.domain_id, src).await{
             Ok(url) => {
                 Ok(url)
             },
-            Err(e) => {
-                Self::create(pool, src).await
+            Err(_) => {
+                Self::create(pool, src, options).await
             }
         }
     }
 
-    pub async fn exists(pool: &SqlitePool, id: i64) -> bool{
-        info!("Url exists");
-        let sql = "SELECT count(*) FROM urls WHERE id = $1";
+    pub async fn exists_slug<'e, E>(executor: E, domain_id: Option<i64>, slug: &str) -> bool
+    where E: Executor<'e, Database = Sqlite>{
+        info!("Url exists_slug");
+        let sql = "SELECT count(*) FROM urls WHERE IFNULL(domain_id, 0) = IFNULL($2, 0)
+                   AND (slug = $1 OR code = $1)";
         debug!("Query: {}", sql);
         match query(sql)
-            .bind(id)
+            .bind(slug)
+            .bind(domain_id)
             .map(|row: SqliteRow| -> i64 {row.get(0)})
-            .fetch_one(pool)
+            .fetch_one(executor)
             .await {
                 Ok(value) => value > 0,
                 Err(e) => {
-                    info!("Error on exists {}", e);
+                    info!("Error on exists_slug {}", e);
                     false
                 }
             }
     }
 
-    pub async fn read(pool: &SqlitePool, id: i64) -> Result<Self, sqlx::Error>{
-        info!("Url read");
-        let sql = "SELECT * FROM urls WHERE id = $1";
+    /// A short path is taken in a domain when it is the slug of one of its
+    /// urls or the base-36 id of one that is named by it.
+    pub async fn is_short_taken<'e, E>(executor: E, domain_id: Option<i64>, short: &str) -> bool
+    where E: Executor<'e, Database = Sqlite>{
+        Self::is_short_taken_except(executor, domain_id, short, None).await
+    }
+
+    /// As `is_short_taken`, but leaving the url `except` out.
+    async fn is_short_taken_except<'e, E>(executor: E, domain_id: Option<i64>, short: &str,
+            except: Option<i64>) -> bool
+    where E: Executor<'e, Database = Sqlite>{
+        info!("Url is_short_taken");
+        let sql = "SELECT count(*) FROM urls WHERE IFNULL(domain_id, 0) = IFNULL($3, 0)
+                   AND (slug = $1 OR code = $1 OR (id = $2 AND slug IS NULL AND code IS NULL))
+                   AND id IS NOT $4";
+        debug!("Query: {}", sql);
+        match query(sql)
+            .bind(short)
+            .bind(Base36.decode(short).ok())
+            .bind(domain_id)
+            .bind(except)
+            .map(|row: SqliteRow| -> i64 {row.get(0)})
+            .fetch_one(executor)
+            .await {
+                Ok(value) => value > 0,
+                Err(e) => {
+                    info!("Error on is_short_taken {}", e);
+                    false
+                }
+            }
+    }
+
+    /// Names a url after its id. It is `RowNotFound` when another url has
+    /// taken the name.
+    async fn set_code(conn: &mut SqliteConnection, id: i64, code: &str) -> Result<Self, sqlx::Error>{
+        info!("Url set_code");
+        let sql = concat!("UPDATE OR IGNORE urls SET code = $2 WHERE id = $1 RETURNING ", columns!());
+        debug!("Query: {}", sql);
+        query(sql)
+            .bind(id)
+            .bind(code)
+            .map(Self::from_row)
+            .fetch_one(conn)
+            .await
+    }
+
+    pub async fn set_slug(pool: &SqlitePool, id: i64, slug: &str) -> Result<Self, sqlx::Error>{
+        info!("Url set_slug");
+        let sql = concat!("UPDATE urls SET slug = $2, updated_at = $3
+                   WHERE id = $1 RETURNING ", columns!());
         debug!("Query: {}", sql);
         query(sql)
             .bind(id)
+            .bind(slug)
+            .bind(Utc::now())
             .map(Self::from_row)
             .fetch_one(pool)
             .await
     }
 
-    pub async fn read_all(pool: &SqlitePool) -> Result<Vec<Self>, sqlx::Error>{
+    pub async fn read(pool: &SqlitePool, id: i64) -> Result<Self, sqlx::Error>{
         info!("Url read");
-        let sql = "SELECT * FROM urls";
+        let sql = concat!("SELECT ", columns!(), " FROM urls WHERE id = $1");
         debug!("Query: {}", sql);
         query(sql)
+            .bind(id)
+            .map(Self::from_row)
+            .fetch_one(pool)
+            .await
+    }
+
+    pub async fn search(pool: &SqlitePool, search: &Search, limit: i64,
+            offset: i64) -> Result<Vec<Self>, sqlx::Error>{
+        info!("Url search");
+        let sql = format!(concat!("SELECT ", columns!(), " FROM urls
+                   WHERE ($1 IS NULL OR src LIKE $1 ESCAPE '\\' OR slug LIKE $1 ESCAPE '\\'
+                   OR title LIKE $1 ESCAPE '\\')
+                   AND ($4 IS NULL OR id IN (", tagged!("$4"), "))
+                   AND ($5 = FALSE OR IFNULL(domain_id, 0) = IFNULL($6, 0))
+                   ORDER BY {column} {order} NULLS LAST, id {order}
+                   LIMIT $2 OFFSET $3"),
+                   column = search.sort.column(), order = search.order.sql());
+        debug!("Query: {}", sql);
+        query(&sql)
+            .bind(search.pattern())
+            .bind(limit)
+            .bind(offset)
+            .bind(&search.tag)
+            .bind(search.domain.is_some())
+            .bind(search.domain.flatten())
             .map(Self::from_row)
             .fetch_all(pool)
             .await
     }
 
+    pub async fn count(pool: &SqlitePool, search: &Search) -> Result<i64, sqlx::Error>{
+        info!("Url count");
+        let sql = concat!("SELECT count(*) FROM urls
+                   WHERE ($1 IS NULL OR src LIKE $1 ESCAPE '\\' OR slug LIKE $1 ESCAPE '\\'
+                   OR title LIKE $1 ESCAPE '\\')
+                   AND ($2 IS NULL OR id IN (", tagged!("$2"), "))
+                   AND ($3 = FALSE OR IFNULL(domain_id, 0) = IFNULL($4, 0))");
+        debug!("Query: {}", sql);
+        query(sql)
+            .bind(search.pattern())
+            .bind(&search.tag)
+            .bind(search.domain.is_some())
+            .bind(search.domain.flatten())
+            .map(|row: SqliteRow| -> i64 {row.get(0)})
+            .fetch_one(pool)
+            .await
+    }
+
+    pub async fn set_active(pool: &SqlitePool, id: i64, active: bool) -> Result<Self, sqlx::Error>{
+        info!("Url set_active");
+        let sql = concat!("UPDATE urls SET active = $2, updated_at = $3
+                   WHERE id = $1 RETURNING ", columns!());
+        debug!("Query: {}", sql);
+        query(sql)
+            .bind(id)
+            .bind(active)
+            .bind(Utc::now())
+            .map(Self::from_row)
+            .fetch_one(pool)
+            .await
+    }
+
+    /// Marks as inactive the urls that have expired, returning how many.
+    pub async fn deactivate_expired(pool: &SqlitePool) -> Result<u64, sqlx::Error>{
+        info!("Url deactivate_expired");
+        let sql = "UPDATE urls SET active = FALSE, updated_at = $1
+                   WHERE active = TRUE AND expires_at <= $1";
+        debug!("Query: {}", sql);
+        query(sql)
+            .bind(Utc::now())
+            .execute(pool)
+            .await
+            .map(|result| result.rows_affected())
+    }
+
+    /// Counts a visit in the database, so the fields of `url` that may be
+    /// stale by now are left as they are.
     pub async fn increase(pool: &SqlitePool, url: &Self) -> Result<Self, sqlx::Error>{
         info!("Url increase");
-        let sql = "UPDATE urls SET num = $2, active = $3,
-                   created_at = $4, updated_at = $5
-                   WHERE id = $1 RETURNING *";
+        let sql = concat!("UPDATE urls SET num = num + 1, updated_at = $2
+                   WHERE id = $1 RETURNING ", columns!());
         debug!("Query: {}", sql);
         query(sql)
             .bind(url.id)
-            .bind(url.num + 1)
-            .bind(url.active)
-            .bind(url.created_at)
             .bind(Utc::now())
             .map(Self::from_row)
             .fetch_one(pool)
             .await
     }
 
+    /// Saves every field of the url, along with its tags, but the visits,
+    /// that only `increase` counts.
     pub async fn update(pool: &SqlitePool, url: Self) -> Result<Self, sqlx::Error>{
         info!("update");
-        let sql = "UPDATE urls SET num = $2, active = $3,
-                   created_at = $4, updated_at = $5 FROM urls
+        let sql = concat!("UPDATE urls SET src = $2, slug = $3, active = $4,
+                   expires_at = $5, fallback_url = $6, password = $7, redirect_type = $8,
+                   forward_query = $9, forward_path = $10, sticky_variant = $11,
+                   title = $12, notes = $13, updated_at = $14
                    WHERE id = $1
-                   RETURNING *";
+                   RETURNING ", columns!());
         debug!("Query: {}", sql);
-        query(sql)
+        let mut tx = pool.begin().await?;
+        Tags::save(&mut tx, url.id, &url.tags).await?;
+        let updated = query(sql)
             .bind(url.id)
-            .bind(url.num)
+            .bind(url.src)
+            .bind(url.slug)
             .bind(url.active)
-            .bind(url.created_at)
+            .bind(url.expires_at)
+            .bind(url.fallback_url)
+            .bind(url.password)
+            .bind(url.redirect_type.as_ref().map(RedirectType::as_str))
+            .bind(url.forward_query)
+            .bind(url.forward_path)
+            .bind(url.sticky_variant)
+            .bind(url.title)
+            .bind(url.notes)
             .bind(Utc::now())
             .map(Self::from_row)
-            .fetch_one(pool)
-            .await
+            .fetch_one(&mut tx)
+            .await?;
+        tx.commit().await?;
+        Ok(updated)
     }
 
-    pub async fn delete(pool: &SqlitePool, id: i64) -> Result<Self, sqlx::Error>{
+    pub async fn delete<'e, E>(executor: E, id: i64) -> Result<Self, sqlx::Error>
+    where E: Executor<'e, Database = Sqlite>{
         info!("Url delete");
-        let sql = "DELETE from urls WHERE id = $1
-                   RETURNING *";
+        let sql = concat!("DELETE from urls WHERE id = $1
+                   RETURNING ", columns!());
         debug!("Query: {}", sql);
         query(sql)
             .bind(id)
             .map(Self::from_row)
-            .fetch_one(pool)
+            .fetch_one(executor)
             .await
     }
 }
@@ -197,7 +1248,21 @@ mod url_test {
         }
     };
     use std::{env, path::Path};
-    use super::Url;
+    use chrono::{Duration, Utc};
+    use futures::TryStreamExt;
+    use crate::model::{
+        domain::{Domain, NewDomain},
+        slug::SlugStrategy,
+    };
+    use super::{ImportRow, ImportStatus, Order, RedirectType, Search, Sort, Tags, Url, UrlOptions, UrlPatch, Utm,
+        Base36, SlugCodec};
+
+    fn slug(slug: &str) -> UrlOptions{
+        UrlOptions{
+            slug: Some(slug.to_string()),
+            ..Default::default()
+        }
+    }
 
     async fn setup(db: &str) -> Pool<Sqlite>{
         let db_url = format!("sqlite:{}", db);
@@ -236,7 +1301,7 @@ mod url_test {
         let pool = setup(db).await;
         // Test
         let src = "https://google.es";
-        let url = Url::create(&pool, src).await.unwrap();
+        let url = Url::create(&pool, src, &UrlOptions::default()).await.unwrap();
         assert!(url.get_src() == src);
         assert!(url.get_num() == 0);
         // End and Clean
@@ -250,10 +1315,15 @@ mod url_test {
         let pool = setup(db).await;
         // Test
         let src = "https://atareao.es";
-        let url = Url::create(&pool, src).await.unwrap();
+        let url = Url::create(&pool, src, &UrlOptions::default()).await.unwrap();
         let new_url = Url::increase(&pool, &url).await.unwrap();
         assert!(new_url.get_src() == src);
         assert!(new_url.get_num() == 1);
+        // A visit that read the url before it was disabled
+        Url::set_active(&pool, url.get_id(), false).await.unwrap();
+        let new_url = Url::increase(&pool, &url).await.unwrap();
+        assert!(!new_url.is_active());
+        assert!(new_url.get_num() == 2);
         // End and Clean
         teardown(db).await;
     }
@@ -265,8 +1335,8 @@ mod url_test {
         let pool = setup(db).await;
         // Test
         let src = "https://atareao.es";
-        let url = Url::create(&pool, src).await.unwrap();
-        let read_url = Url::read_from_url(&pool, src).await.unwrap();
+        let url = Url::create(&pool, src, &UrlOptions::default()).await.unwrap();
+        let read_url = Url::read_from_url(&pool, None, src).await.unwrap();
         assert!(read_url.get_src() == url.get_src());
         assert!(read_url.get_num() == url.get_num());
         // End and Clean
@@ -280,11 +1350,557 @@ mod url_test {
         let pool = setup(db).await;
         // Test
         let src = "https://atareao.es";
-        let url = Url::create(&pool, src).await.unwrap();
+        let url = Url::create(&pool, src, &UrlOptions::default()).await.unwrap();
         let _result = Url::delete(&pool, url.id).await;
-        assert!(Url::exists(&pool, url.id).await == false);
+        assert!(Url::read(&pool, url.id).await.is_err());
+        // End and Clean
+        teardown(db).await;
+    }
+
+    #[tokio::test]
+    async fn test_read_from_short(){
+        let db = "test-read-from-short.db";
+        // Start and prepare
+        let pool = setup(db).await;
+        // Test
+        let url = Url::create(&pool, "https://atareao.es", &UrlOptions::default()).await.unwrap();
+        let custom = Url::create(&pool, "https://google.es", &slug("launch2026")).await.unwrap();
+        assert!(custom.get_url() == "launch2026");
+        let read_url = Url::read_from_short(&pool, None, "launch2026").await.unwrap();
+        assert!(read_url.get_src() == custom.get_src());
+        let read_url = Url::read_from_short(&pool, None, &url.get_url()).await.unwrap();
+        assert!(read_url.get_src() == url.get_src());
+        assert!(Url::read_from_short(&pool, None, "favicon.png").await.is_err());
+        // End and Clean
+        teardown(db).await;
+    }
+
+    #[tokio::test]
+    async fn test_is_short_taken(){
+        let db = "test-is-short-taken.db";
+        // Start and prepare
+        let pool = setup(db).await;
+        // Test
+        let url = Url::create(&pool, "https://atareao.es", &UrlOptions::default()).await.unwrap();
+        Url::create(&pool, "https://google.es", &slug("docs")).await.unwrap();
+        assert!(Url::is_short_taken(&pool, None, "docs").await);
+        assert!(Url::is_short_taken(&pool, None, &url.get_url()).await);
+        assert!(!Url::is_short_taken(&pool, None, "launch2026").await);
+        // End and Clean
+        teardown(db).await;
+    }
+
+    #[tokio::test]
+    async fn test_slug_strategy(){
+        let db = "test-slug-strategy.db";
+        // Start and prepare
+        let pool = setup(db).await;
+        let old = Url::create(&pool, "https://atareao.es", &UrlOptions::default()).await.unwrap();
+        let random = UrlOptions{
+            strategy: SlugStrategy::Random{ length: 9, alphabet: "abcdefghijklmnop".to_string() },
+            ..Default::default()
+        };
+        let obfuscated = UrlOptions{
+            strategy: SlugStrategy::Obfuscated{
+                salt: "secret".to_string(),
+                alphabet: "abcdefghijklmnopqrstuvwxyz0123456789".to_string(),
+                min_length: 6,
+            },
+            ..Default::default()
+        };
+        // Test
+        let url = Url::create(&pool, "https://google.es", &random).await.unwrap();
+        assert!(url.get_url().len() == 9);
+        assert!(url.get_slug().is_none());
+        let read_url = Url::read_from_short(&pool, None, &url.get_url()).await.unwrap();
+        assert!(read_url.get_id() == url.get_id());
+        let id = Base36.encode(url.get_id()).unwrap();
+        assert!(Url::read_from_short(&pool, None, &id).await.is_err());
+        let url = Url::create(&pool, "https://duckduckgo.com", &obfuscated).await.unwrap();
+        assert!(url.get_url().len() >= 6);
+        assert!(Some(url.get_url()) == obfuscated.strategy.name(url.get_id()).unwrap());
+        assert!(Url::is_short_taken(&pool, None, &url.get_url()).await);
+        let read_url = Url::read_from_short(&pool, None, &url.get_url()).await.unwrap();
+        assert!(read_url.get_id() == url.get_id());
+        let custom = UrlOptions{ slug: Some("rust".to_string()), ..random };
+        let url = Url::create(&pool, "https://rust-lang.org", &custom).await.unwrap();
+        let id = Base36.encode(url.get_id()).unwrap();
+        assert!(Url::read_from_short(&pool, None, &id).await.is_err());
+        let base62 = UrlOptions{ strategy: SlugStrategy::Sequential{ base62: true }, ..Default::default() };
+        let url = Url::create(&pool, "https://crates.io", &base62).await.unwrap();
+        assert!(url.get_id() == 5 && url.get_url() == "5");
+        // Up to an id base-36 writes differently, with custom slugs so the
+        // base-36 ids are free
+        for n in 6..61{
+            let custom = UrlOptions{ slug: Some(format!("crate-{}", n)), ..Default::default() };
+            Url::create(&pool, &format!("https://crates.io/{}", n), &custom).await.unwrap();
+        }
+        let url = Url::create(&pool, "https://docs.rs", &base62).await.unwrap();
+        assert!(url.get_id() == 61 && url.get_url() == "Z");
+        let read_url = Url::read_from_short(&pool, None, "Z").await.unwrap();
+        assert!(read_url.get_id() == 61);
+        assert!(Url::read_from_short(&pool, None, "z").await.is_err());
+        // The links named by their ids keep working
+        let read_url = Url::read_from_short(&pool, None, &old.get_url()).await.unwrap();
+        assert!(read_url.get_id() == old.get_id());
+        // End and Clean
+        teardown(db).await;
+    }
+
+    #[tokio::test]
+    async fn test_domains(){
+        let db = "test-url-domains.db";
+        // Start and prepare
+        let pool = setup(db).await;
+        let new_domain = NewDomain{
+            host: "brand.link".to_string(),
+            root_url: None,
+            not_found_url: None,
+        };
+        let brand = Some(Domain::create(&pool, &new_domain).await.unwrap().get_id());
+        // Test
+        let src = "https://atareao.es";
+        let url = Url::create(&pool, src, &slug("docs")).await.unwrap();
+        let options = UrlOptions{ domain_id: brand, ..slug("docs") };
+        let branded = Url::create(&pool, src, &options).await.unwrap();
+        assert!(branded.get_domain() == Some("brand.link"));
+        let read_url = Url::read_from_short(&pool, brand, "docs").await.unwrap();
+        assert!(read_url.get_id() == branded.get_id());
+        let read_url = Url::read_from_url(&pool, None, src).await.unwrap();
+        assert!(read_url.get_id() == url.get_id());
+        let plain = Url::create(&pool, "https://google.es", &UrlOptions::default()).await.unwrap();
+        assert!(Url::read_from_short(&pool, brand, &plain.get_url()).await.is_err());
+        assert!(!Url::is_short_taken(&pool, brand, &plain.get_url()).await);
+        assert!(Url::create(&pool, "https://google.es", &options).await.is_err());
+        let search = Search{ domain: Some(brand), ..Default::default() };
+        let page = Url::search(&pool, &search, 10, 0).await.unwrap();
+        assert!(page.len() == 1 && page[0].get_id() == branded.get_id());
+        let search = Search{ domain: Some(None), ..Default::default() };
+        assert!(Url::count(&pool, &search).await.unwrap() == 2);
+        assert!(Url::count(&pool, &Search::default()).await.unwrap() == 3);
+        // End and Clean
+        teardown(db).await;
+    }
+
+    #[tokio::test]
+    async fn test_create_skips_taken_ids(){
+        let db = "test-create-skips-taken-ids.db";
+        // Start and prepare
+        let pool = setup(db).await;
+        // Test
+        Url::create(&pool, "https://google.es", &slug("2")).await.unwrap();
+        let url = Url::create(&pool, "https://atareao.es", &UrlOptions::default()).await.unwrap();
+        assert!(url.get_url() != "2");
+        let read_url = Url::read_from_short(&pool, None, "2").await.unwrap();
+        assert!(read_url.get_src() == "https://google.es");
+        // A name taken by a concurrent insert is reported, not overwritten
+        let other = Url::create(&pool, "https://duck.com", &slug("duck")).await.unwrap();
+        let mut conn = pool.acquire().await.unwrap();
+        Url::set_code(&mut conn, url.get_id(), "name").await.unwrap();
+        assert!(matches!(Url::set_code(&mut conn, other.get_id(), "name").await,
+            Err(sqlx::Error::RowNotFound)));
+        // End and Clean
+        teardown(db).await;
+    }
+
+    #[tokio::test]
+    async fn test_deactivate_expired(){
+        let db = "test-deactivate-expired.db";
+        // Start and prepare
+        let pool = setup(db).await;
+        // Test
+        let options = UrlOptions{
+            expires_at: Some(Utc::now() - Duration::minutes(1)),
+            fallback_url: Some("https://atareao.es/fallback".to_string()),
+            ..Default::default()
+        };
+        let expired = Url::create(&pool, "https://atareao.es", &options).await.unwrap();
+        let options = UrlOptions{
+            expires_at: Some(Utc::now() + Duration::days(1)),
+            ..Default::default()
+        };
+        let url = Url::create(&pool, "https://google.es", &options).await.unwrap();
+        assert!(expired.is_expired());
+        assert!(expired.get_fallback_url() == Some("https://atareao.es/fallback"));
+        assert!(!url.is_expired());
+        assert!(Url::deactivate_expired(&pool).await.unwrap() == 1);
+        assert!(!Url::read(&pool, expired.get_id()).await.unwrap().is_active());
+        assert!(Url::read(&pool, url.get_id()).await.unwrap().is_active());
+        // End and Clean
+        teardown(db).await;
+    }
+
+    #[tokio::test]
+    async fn test_set_active(){
+        let db = "test-set-active.db";
+        // Start and prepare
+        let pool = setup(db).await;
+        // Test
+        let url = Url::create(&pool, "https://atareao.es", &UrlOptions::default()).await.unwrap();
+        assert!(url.is_active());
+        let disabled = Url::set_active(&pool, url.get_id(), false).await.unwrap();
+        assert!(!disabled.is_active());
+        assert!(disabled.get_num() == url.get_num());
+        let enabled = Url::set_active(&pool, url.get_id(), true).await.unwrap();
+        assert!(enabled.is_active());
+        // End and Clean
+        teardown(db).await;
+    }
+
+    #[tokio::test]
+    async fn test_update(){
+        let db = "test-update.db";
+        // Start and prepare
+        let pool = setup(db).await;
+        // Test
+        let options = UrlOptions{
+            expires_at: Some(Utc::now() + Duration::days(1)),
+            ..Default::default()
+        };
+        let mut url = Url::create(&pool, "https://atareao.es", &options).await.unwrap();
+        // A visit counted while the patch is applied
+        Url::increase(&pool, &url).await.unwrap();
+        url.apply(UrlPatch{
+            src: Some("https://atareao.es/blog".to_string()),
+            expires_at: Some(None),
+            ..Default::default()
+        });
+        let updated = Url::update(&pool, url).await.unwrap();
+        assert!(updated.get_src() == "https://atareao.es/blog");
+        assert!(updated.expires_at.is_none());
+        assert!(updated.is_active());
+        assert!(updated.get_num() == 1);
+        // End and Clean
+        teardown(db).await;
+    }
+
+    #[tokio::test]
+    async fn test_search(){
+        let db = "test-search.db";
+        // Start and prepare
+        let pool = setup(db).await;
+        // Test
+        for src in ["https://atareao.es", "https://google.es", "https://duckduckgo.com"]{
+            Url::create(&pool, src, &UrlOptions::default()).await.unwrap();
+        }
+        Url::create(&pool, "https://atareao.es/100%_rust", &slug("rust")).await.unwrap();
+        let search = Search{ sort: Sort::Src, order: Order::Asc, ..Default::default() };
+        let page = Url::search(&pool, &search, 2, 2).await.unwrap();
+        assert!(page.len() == 2);
+        assert!(page[0].get_src() == "https://duckduckgo.com");
+        assert!(Url::count(&pool, &search).await.unwrap() == 4);
+        let search = Search{ query: Some("atareao".to_string()), ..Default::default() };
+        let page = Url::search(&pool, &search, 10, 0).await.unwrap();
+        assert!(page.len() == 2);
+        assert!(page[0].get_slug() == Some("rust"));
+        let search = Search{ query: Some("0%_r".to_string()), ..Default::default() };
+        assert!(Url::count(&pool, &search).await.unwrap() == 1);
+        let search = Search{ query: Some("o%e".to_string()), ..Default::default() };
+        assert!(Url::count(&pool, &search).await.unwrap() == 0);
+        // End and Clean
+        teardown(db).await;
+    }
+
+    fn row(src: &str, short: Option<&str>) -> Result<ImportRow, String>{
+        Ok(ImportRow{
+            src: src.to_string(),
+            short: short.map(|short| short.to_string()),
+            num: 7,
+            active: true,
+            expires_at: None,
+            fallback_url: None,
+            protected: false,
+            password: None,
+            redirect_type: None,
+            forward_query: false,
+            forward_path: false,
+            utm_source: None,
+            utm_medium: None,
+            utm_campaign: None,
+            utm_term: None,
+            utm_content: None,
+            utm_on_redirect: false,
+            sticky_variant: false,
+            title: None,
+            notes: None,
+            tags: Default::default(),
+            domain: None,
+        })
+    }
+
+    #[tokio::test]
+    async fn test_import(){
+        let db = "test-import.db";
+        // Start and prepare
+        let pool = setup(db).await;
+        Url::create(&pool, "https://atareao.es", &slug("blog")).await.unwrap();
+        // Test
+        let results = Url::import(&pool, &SlugStrategy::default(), vec![
+            row("https://google.es", Some("search")),
+            row("https://atareao.es", None),
+            row("https://duckduckgo.com", Some("blog")),
+            row("https://rust-lang.org", Some("_rust")),
+            Err("missing field `src`".to_string()),
+            row("https://crates.io", None),
+        ]).await.unwrap();
+        let statuses: Vec<&ImportStatus> = results.iter().map(|result| &result.status).collect();
+        assert!(*statuses[0] == ImportStatus::Created{short: "search".to_string()});
+        assert!(*statuses[1] == ImportStatus::Duplicate{short: "blog".to_string()});
+        assert!(*statuses[2] == ImportStatus::Duplicate{short: "blog".to_string()});
+        assert!(matches!(statuses[3], ImportStatus::Invalid{..}));
+        assert!(matches!(statuses[4], ImportStatus::Invalid{..}));
+        assert!(matches!(statuses[5], ImportStatus::Created{..}));
+        assert!(results[5].row == 6);
+        let imported = Url::read_from_slug(&pool, None, "search").await.unwrap();
+        assert!(imported.get_num() == 7);
+        // End and Clean
+        teardown(db).await;
+    }
+
+    #[tokio::test]
+    async fn test_import_domain(){
+        let db = "test-import-domain.db";
+        // Start and prepare
+        let pool = setup(db).await;
+        let new_domain = NewDomain{
+            host: "brand.link".to_string(),
+            root_url: None,
+            not_found_url: None,
+        };
+        let brand = Some(Domain::create(&pool, &new_domain).await.unwrap().get_id());
+        let with_domain = |src: &str, domain: &str| row(src, Some("promo")).map(|row| ImportRow{
+            domain: Some(domain.to_string()),
+            ..row
+        });
+        // Test
+        let results = Url::import(&pool, &SlugStrategy::default(), vec![
+            with_domain("https://google.es", "Brand.LINK:443"),
+            with_domain("https://atareao.es", "other.link"),
+        ]).await.unwrap();
+        assert!(results[0].status == ImportStatus::Created{short: "promo".to_string()});
+        assert!(matches!(results[1].status, ImportStatus::Invalid{..}));
+        let imported = Url::read_from_short(&pool, brand, "promo").await.unwrap();
+        assert!(imported.get_src() == "https://google.es");
+        // End and Clean
+        teardown(db).await;
+    }
+
+    #[tokio::test]
+    async fn test_read_all(){
+        let db = "test-read-all.db";
+        // Start and prepare
+        let pool = setup(db).await;
+        Url::create(&pool, "https://atareao.es", &UrlOptions::default()).await.unwrap();
+        Url::create(&pool, "https://google.es", &UrlOptions::default()).await.unwrap();
+        // Test
+        let urls: Vec<Url> = Url::read_all(&pool, None).try_collect().await.unwrap();
+        assert!(urls.len() == 2);
+        assert!(urls[0].get_src() == "https://atareao.es");
+        // End and Clean
+        teardown(db).await;
+    }
+
+    #[tokio::test]
+    async fn test_password(){
+        let db = "test-password.db";
+        // Start and prepare
+        let pool = setup(db).await;
+        let options = UrlOptions{
+            password: Some("$argon2id$v=19$m=19456,t=2,p=1$c2FsdA$aGFzaA".to_string()),
+            ..Default::default()
+        };
+        // Test
+        let mut url = Url::create(&pool, "https://atareao.es", &options).await.unwrap();
+        assert!(url.is_protected());
+        assert!(url.get_password() == options.password.as_deref());
+        url.apply(UrlPatch{password: Some(None), ..Default::default()});
+        let url = Url::update(&pool, url).await.unwrap();
+        assert!(!url.is_protected());
+        // End and Clean
+        teardown(db).await;
+    }
+
+    #[tokio::test]
+    async fn test_import_password(){
+        let db = "test-import-password.db";
+        // Start and prepare
+        let pool = setup(db).await;
+        let hash = "$argon2id$v=19$m=19456,t=2,p=1$wfSzln/76hIPO60x5uUrQA$r/f5qMpoJ2yinfRZ2WMdDASML7VpoI0TfmMb0r1zvb4";
+        let options = UrlOptions{
+            password: Some(hash.to_string()),
+            ..Default::default()
+        };
+        let url = Url::create(&pool, "https://atareao.es", &options).await.unwrap();
+        let exported = serde_json::to_string(&url.get_export()).unwrap();
+        assert!(exported.contains(hash));
+        assert!(!serde_json::to_string(&url.get_short()).unwrap().contains(hash));
+        let with_password = |src: &str, password: Option<&str>| row(src, Some("secret")).map(|row| ImportRow{
+            protected: true,
+            password: password.map(|password| password.to_string()),
+            ..row
+        });
+        // Test
+        let results = Url::import(&pool, &SlugStrategy::default(), vec![
+            with_password("https://google.es", Some(hash)),
+            with_password("https://duckduckgo.com", None),
+            with_password("https://rust-lang.org", Some("plain")),
+        ]).await.unwrap();
+        assert!(results[0].status == ImportStatus::Created{short: "secret".to_string()});
+        assert!(matches!(results[1].status, ImportStatus::Invalid{..}));
+        assert!(matches!(results[2].status, ImportStatus::Invalid{..}));
+        let imported = Url::read_from_short(&pool, None, "secret").await.unwrap();
+        assert!(imported.get_password() == Some(hash));
+        // End and Clean
+        teardown(db).await;
+    }
+
+    #[tokio::test]
+    async fn test_redirect_type(){
+        let db = "test-redirect-type.db";
+        // Start and prepare
+        let pool = setup(db).await;
+        let options = UrlOptions{
+            redirect_type: Some(RedirectType::PermanentRedirect),
+            ..Default::default()
+        };
+        // Test
+        let mut url = Url::create(&pool, "https://atareao.es", &options).await.unwrap();
+        assert!(url.get_redirect_type() == Some(RedirectType::PermanentRedirect));
+        url.apply(serde_json::from_str::<UrlPatch>(r#"{"redirect_type": "refresh"}"#).unwrap());
+        let mut url = Url::update(&pool, url).await.unwrap();
+        assert!(url.get_redirect_type() == Some(RedirectType::Refresh));
+        url.apply(serde_json::from_str::<UrlPatch>(r#"{"redirect_type": null}"#).unwrap());
+        let url = Url::update(&pool, url).await.unwrap();
+        assert!(url.get_redirect_type().is_none());
+        // End and Clean
+        teardown(db).await;
+    }
+
+    #[tokio::test]
+    async fn test_destination(){
+        let db = "test-destination.db";
+        // Start and prepare
+        let pool = setup(db).await;
+        let options = UrlOptions{
+            forward_query: true,
+            forward_path: true,
+            ..Default::default()
+        };
+        // Test
+        let url = Url::create(&pool, "https://github.com/?tab=repos", &options).await.unwrap();
+        assert!(url.get_destination(None, None, None) == "https://github.com/?tab=repos");
+        assert!(url.get_destination(None, Some("rust-lang/rust"), None)
+            == "https://github.com/rust-lang/rust?tab=repos");
+        assert!(url.get_destination(None, Some("../a b"), Some("ref=news&tab=stars"))
+            == "https://github.com/a%20b?tab=repos&ref=news");
+        let url = Url::create(&pool, "https://atareao.es/blog#top", &UrlOptions::default()).await.unwrap();
+        assert!(url.get_destination(None, Some("extra"), Some("ref=news")) == "https://atareao.es/blog#top");
         // End and Clean
         teardown(db).await;
     }
-}
 
+    #[tokio::test]
+    async fn test_utm(){
+        let db = "test-utm.db";
+        // Start and prepare
+        let pool = setup(db).await;
+        let utm = Utm{
+            source: Some(" newsletter ".to_string()),
+            medium: Some("".to_string()),
+            campaign: Some("spring sale".to_string()),
+            ..Default::default()
+        }.clean();
+        let options = UrlOptions{
+            utm: utm.clone(),
+            utm_on_redirect: true,
+            ..Default::default()
+        };
+        // Test
+        assert!(utm.tag("https://atareao.es/?utm_source=web")
+            == "https://atareao.es/?utm_source=web&utm_campaign=spring+sale");
+        let tagged = Url::create(&pool, "https://atareao.es/", &options).await.unwrap();
+        assert!(tagged.get_destination(None, None, None)
+            == "https://atareao.es/?utm_source=newsletter&utm_campaign=spring+sale");
+        assert!(Url::read_from_url(&pool, None, "https://atareao.es/").await.is_err());
+        let found = Url::read_from_tagged_url(&pool, None, "https://atareao.es/", &utm).await.unwrap();
+        assert!(found.get_id() == tagged.get_id());
+        let options = UrlOptions{ utm, ..Default::default() };
+        Url::create(&pool, "https://google.es/", &options).await.unwrap();
+        Url::create(&pool, "https://duck.com/", &UrlOptions::default()).await.unwrap();
+        let campaigns = Url::read_campaigns(&pool).await.unwrap();
+        assert!(campaigns.len() == 1);
+        assert!(campaigns[0].name == "spring sale");
+        assert!(campaigns[0].links.len() == 2);
+        // End and Clean
+        teardown(db).await;
+    }
+
+    #[tokio::test]
+    async fn test_campaigns_share_src(){
+        let db = "test-campaigns-share-src.db";
+        // Start and prepare
+        let pool = setup(db).await;
+        let src = "https://atareao.es/";
+        let campaign = |name: &str| UrlOptions{
+            utm: Utm{ campaign: Some(name.to_string()), ..Default::default() },
+            utm_on_redirect: true,
+            ..Default::default()
+        };
+        // Test
+        let plain = Url::create(&pool, src, &UrlOptions::default()).await.unwrap();
+        let spring = Url::create(&pool, src, &campaign("spring")).await.unwrap();
+        let summer = Url::create(&pool, src, &campaign("summer")).await.unwrap();
+        assert!(plain.get_id() != spring.get_id() && spring.get_id() != summer.get_id());
+        let found = Url::read_from_tagged_url(&pool, None, src, &campaign("summer").utm).await.unwrap();
+        assert!(found.get_id() == summer.get_id());
+        assert!(Url::read_from_url(&pool, None, src).await.unwrap().get_id() == plain.get_id());
+        // A second plain link for the same destination is still refused
+        assert!(Url::create(&pool, src, &UrlOptions::default()).await.is_err());
+        // End and Clean
+        teardown(db).await;
+    }
+
+    #[tokio::test]
+    async fn test_tags(){
+        let db = "test-tags.db";
+        // Start and prepare
+        let pool = setup(db).await;
+        let options = UrlOptions{
+            title: Some("Rust".to_string()),
+            tags: serde_json::from_str(r#"["Rust", " web", "rust", ""]"#).unwrap(),
+            ..Default::default()
+        };
+        // Test
+        assert!(options.tags == Tags::parse("rust,web"));
+        assert!(serde_json::to_string(&options.tags).unwrap() == r#""rust,web""#);
+        assert!(serde_json::from_str::<Tags>(r#"["a,b"]"#).is_err());
+        let rust = Url::create(&pool, "https://rust-lang.org", &options).await.unwrap();
+        assert!(rust.tags == options.tags && rust.title.as_deref() == Some("Rust"));
+        let options = UrlOptions{ tags: Tags::parse("web"), ..Default::default() };
+        let duck = Url::create(&pool, "https://duckduckgo.com", &options).await.unwrap();
+        Url::create(&pool, "https://atareao.es", &UrlOptions::default()).await.unwrap();
+        let read = Url::read(&pool, rust.get_id()).await.unwrap();
+        assert!(read.tags == Tags::parse("rust,web"));
+        let search = Search{ tag: Some("web".to_string()), ..Default::default() };
+        assert!(Url::count(&pool, &search).await.unwrap() == 2);
+        let search = Search{ tag: Some("rust".to_string()), ..Default::default() };
+        let page = Url::search(&pool, &search, 10, 0).await.unwrap();
+        assert!(page.len() == 1 && page[0].get_id() == rust.get_id());
+        let search = Search{ query: Some("rus".to_string()), ..Default::default() };
+        assert!(Url::count(&pool, &search).await.unwrap() == 1);
+        let exported: Vec<Url> = Url::read_all(&pool, Some("web")).try_collect().await.unwrap();
+        assert!(exported.len() == 2);
+        let mut url = duck;
+        url.apply(UrlPatch{ tags: Some(Tags::parse("search")), ..Default::default() });
+        let url = Url::update(&pool, url).await.unwrap();
+        assert!(url.tags == Tags::parse("search"));
+        let tags = Tags::read_all(&pool).await.unwrap();
+        assert!(tags.iter().map(|tag| (tag.name.as_str(), tag.links)).collect::<Vec<_>>()
+            == vec![("rust", 1), ("search", 1), ("web", 1)]);
+        Url::delete(&pool, rust.get_id()).await.unwrap();
+        Url::update(&pool, url).await.unwrap();
+        assert!(Tags::read_all(&pool).await.unwrap().len() == 1);
+        // End and Clean
+        teardown(db).await;
+    }
+}
@@ -0,0 +1,184 @@
+use serde::{Serialize, Deserialize};
+use sqlx::{sqlite::{SqlitePool, SqliteRow}, query, Row};
+use chrono::{DateTime, Utc};
+use rand::Rng;
+use tracing::{debug, info};
+
+
+/// One of the destinations a url splits its visits between. Every visit
+/// goes to one of them, with a chance proportional to its weight.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Variant{
+    id: i64,
+    url_id: i64,
+    src: String,
+    weight: u32,
+    created_at: DateTime<Utc>,
+}
+
+/// The destination and the weight of a new variant.
+#[derive(Debug, Deserialize)]
+pub struct NewVariant{
+    pub src: String,
+    #[serde(default = "default_weight")]
+    pub weight: u32,
+}
+
+fn default_weight() -> u32{
+    1
+}
+
+impl Variant{
+    pub fn get_id(&self) -> i64{
+        self.id
+    }
+    pub fn get_src(&self) -> &str{
+        &self.src
+    }
+
+    /// Picks one of `variants` at random, by weight.
+    pub fn choose(variants: &[Self]) -> Option<&Self>{
+        let total: u64 = variants.iter().map(|variant| u64::from(variant.weight)).sum();
+        if total == 0{
+            return None;
+        }
+        Self::pick(variants, rand::thread_rng().gen_range(0..total))
+    }
+
+    /// The variant that owns `point` when the weights are laid one after
+    /// the other.
+    fn pick(variants: &[Self], mut point: u64) -> Option<&Self>{
+        variants.iter().find(|variant| {
+            let weight = u64::from(variant.weight);
+            if point < weight{
+                return true;
+            }
+            point -= weight;
+            false
+        })
+    }
+
+    fn from_row(row: SqliteRow) -> Self{
+        Self{
+            id: row.get("id"),
+            url_id: row.get("url_id"),
+            src: row.get("src"),
+            weight: row.get("weight"),
+            created_at: row.get("created_at"),
+        }
+    }
+
+    pub async fn create(pool: &SqlitePool, url_id: i64, variant: &NewVariant) -> Result<Self, sqlx::Error>{
+        info!("Variant create");
+        let sql = "INSERT INTO variants (url_id, src, weight, created_at)
+                   VALUES($1, $2, $3, $4) RETURNING *";
+        debug!("Query: {}", sql);
+        query(sql)
+            .bind(url_id)
+            .bind(&variant.src)
+            .bind(variant.weight)
+            .bind(Utc::now())
+            .map(Self::from_row)
+            .fetch_one(pool)
+            .await
+    }
+
+    pub async fn read_for_url(pool: &SqlitePool, url_id: i64) -> Result<Vec<Self>, sqlx::Error>{
+        info!("Variant read_for_url");
+        let sql = "SELECT * FROM variants WHERE url_id = $1 ORDER BY id";
+        debug!("Query: {}", sql);
+        query(sql)
+            .bind(url_id)
+            .map(Self::from_row)
+            .fetch_all(pool)
+            .await
+    }
+
+    pub async fn delete(pool: &SqlitePool, url_id: i64, id: i64) -> Result<Self, sqlx::Error>{
+        info!("Variant delete");
+        let sql = "DELETE FROM variants WHERE id = $1 AND url_id = $2
+                   RETURNING *";
+        debug!("Query: {}", sql);
+        query(sql)
+            .bind(id)
+            .bind(url_id)
+            .map(Self::from_row)
+            .fetch_one(pool)
+            .await
+    }
+}
+
+#[cfg(test)]
+mod variant_test {
+    use sqlx::{
+        self,
+        Pool,
+        sqlite::{
+            Sqlite,
+            SqlitePoolOptions,
+        },
+        migrate::{
+            Migrator,
+            MigrateDatabase
+        }
+    };
+    use std::{env, path::Path};
+    use crate::model::url::{Url, UrlOptions};
+    use super::{NewVariant, Variant};
+
+    async fn setup(db: &str) -> Pool<Sqlite>{
+        let db_url = format!("sqlite:{}", db);
+        teardown(db).await;
+        if !sqlx::Sqlite::database_exists(&db_url).await.unwrap(){
+            sqlx::Sqlite::create_database(&db_url).await.unwrap();
+        }
+        let crate_dir = env::var("CARGO_MANIFEST_DIR").unwrap();
+        let migrations = Path::new(&crate_dir).join("./migrations");
+        let pool = SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect(&db_url)
+            .await
+            .expect("Pool failed");
+
+        Migrator::new(migrations)
+            .await
+            .unwrap()
+            .run(&pool)
+            .await
+        .unwrap();
+        pool
+    }
+
+    #[allow(unused_must_use)]
+    async fn teardown(db: &str) {
+        tokio::fs::remove_file(db).await;
+        tokio::fs::remove_file(format!("{}-shm", db)).await;
+        tokio::fs::remove_file(format!("{}-wal", db)).await;
+    }
+
+    #[tokio::test]
+    async fn test_variants(){
+        let db = "test-variants.db";
+        // Start and prepare
+        let pool = setup(db).await;
+        let url = Url::create(&pool, "https://atareao.es", &UrlOptions::default()).await.unwrap();
+        let new_variant = |src: &str, weight| NewVariant{ src: src.to_string(), weight };
+        // Test
+        let a = Variant::create(&pool, url.get_id(), &new_variant("https://atareao.es/a", 1)).await.unwrap();
+        let b = Variant::create(&pool, url.get_id(), &new_variant("https://atareao.es/b", 3)).await.unwrap();
+        let variants = Variant::read_for_url(&pool, url.get_id()).await.unwrap();
+        assert!(variants.len() == 2);
+        let picked = |point| Variant::pick(&variants, point).map(Variant::get_id);
+        assert!(picked(0) == Some(a.get_id()));
+        assert!(picked(1) == Some(b.get_id()) && picked(3) == Some(b.get_id()));
+        assert!(picked(4).is_none());
+        assert!(Variant::choose(&variants).is_some());
+        assert!(Variant::choose(&[]).is_none());
+        assert!(Variant::delete(&pool, url.get_id() + 1, a.get_id()).await.is_err());
+        Variant::delete(&pool, url.get_id(), a.get_id()).await.unwrap();
+        Url::delete(&pool, url.get_id()).await.unwrap();
+        assert!(Variant::read_for_url(&pool, url.get_id()).await.unwrap().is_empty());
+        // End and Clean
+        teardown(db).await;
+    }
+}
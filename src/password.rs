@@ -0,0 +1,60 @@
+use argon2::{
+    Algorithm,
+    Argon2,
+    Params,
+    Version,
+    password_hash::{
+        self,
+        rand_core::OsRng,
+        PasswordHash,
+        PasswordHasher,
+        PasswordVerifier,
+        SaltString,
+    },
+};
+
+/// Hashes a password with argon2 and a random salt. The result is a PHC
+/// string, `$argon2id$v=19$...`, ready to be stored.
+pub fn hash(password: &str) -> Result<String, password_hash::Error>{
+    let salt = SaltString::generate(&mut OsRng);
+    Argon2::default()
+        .hash_password(password.as_bytes(), &salt)
+        .map(|hash| hash.to_string())
+}
+
+pub fn verify(password: &str, hash: &str) -> bool{
+    match PasswordHash::new(hash){
+        Ok(parsed) => Argon2::default()
+            .verify_password(password.as_bytes(), &parsed)
+            .is_ok(),
+        Err(_) => false,
+    }
+}
+
+/// Whether `value` is an argon2 hash that `verify` can check, with its
+/// salt and its output. Other PHC strings, such as bcrypt or scrypt ones,
+/// are not.
+pub fn is_hash(value: &str) -> bool{
+    let hash = match PasswordHash::new(value){
+        Ok(hash) => hash,
+        Err(_) => return false,
+    };
+    Algorithm::try_from(hash.algorithm).is_ok()
+        && hash.version.map_or(true, |version| Version::try_from(version).is_ok())
+        && Params::try_from(&hash).is_ok()
+        && hash.salt.is_some()
+        && hash.hash.is_some()
+}
+
+#[test]
+fn hash_and_verify(){
+    let hash = hash("conraseña").unwrap();
+    assert!(is_hash(&hash));
+    assert!(verify("conraseña", &hash));
+    assert!(!verify("contraseña", &hash));
+    assert!(!verify("conraseña", "conraseña"));
+    assert!(!is_hash("conraseña"));
+    assert!(!is_hash("$scrypt$ln=16,r=8,p=1$aM15713r3Xsvxbi31lqr1Q$nFNh2CVHVjNldFVKDHDlm4CbdRSCdEBsjjJxD+iCs5E"));
+    assert!(!is_hash("$argon2id$v=19$m=0,t=2,p=1$wfSzln/76hIPO60x5uUrQA$r/f5qMpoJ2yinfRZ2WMdDASML7VpoI0TfmMb0r1zvb4"));
+    assert!(!is_hash("$argon2id$v=19$m=19456,t=2,p=1$wfSzln/76hIPO60x5uUrQA"));
+}
@@ -0,0 +1,116 @@
+use std::{
+    path::PathBuf,
+    sync::{Arc, RwLock},
+    time::{Duration, SystemTime},
+};
+use serde::Deserialize;
+use tracing::{info, error};
+
+/// Seconds between the checks for changes in the domain policy file
+const RELOAD_INTERVAL: u64 = 10;
+
+
+/// Which destinations can be shortened and visited, by domain. A pattern
+/// is a domain, that matches only itself, or `*.domain`, that matches every
+/// subdomain of it. When the allowlist is not empty only the domains in it
+/// are accepted, and the blocklist is checked first anyway.
+#[derive(Debug, Default, Deserialize)]
+pub struct DomainPolicy{
+    #[serde(default)]
+    blocklist: Vec<String>,
+    #[serde(default)]
+    allowlist: Vec<String>,
+}
+
+pub type SharedPolicy = Arc<RwLock<DomainPolicy>>;
+
+fn matches(pattern: &str, host: &str) -> bool{
+    match pattern.strip_prefix("*."){
+        Some(domain) => host.len() > domain.len() + 1
+            && host.ends_with(domain)
+            && host[..host.len() - domain.len()].ends_with('.'),
+        None => host == pattern,
+    }
+}
+
+impl DomainPolicy{
+    pub fn new(content: &str) -> Result<Self, serde_yaml::Error>{
+        let mut policy: Self = serde_yaml::from_str(content)?;
+        for pattern in policy.blocklist.iter_mut().chain(policy.allowlist.iter_mut()){
+            *pattern = pattern.trim().trim_end_matches('.').to_lowercase();
+        }
+        Ok(policy)
+    }
+
+    pub async fn read(path: &str) -> anyhow::Result<Self>{
+        let content = tokio::fs::read_to_string(path).await?;
+        Ok(Self::new(&content)?)
+    }
+
+    pub fn allows(&self, host: &str) -> bool{
+        let host = host.trim_end_matches('.').to_lowercase();
+        !self.blocklist.iter().any(|pattern| matches(pattern, &host))
+            && (self.allowlist.is_empty()
+                || self.allowlist.iter().any(|pattern| matches(pattern, &host)))
+    }
+
+    /// Checks the host of a url. Anything without a host is left to the
+    /// validation of urls.
+    pub fn allows_url(&self, src: &str) -> bool{
+        match url::Url::parse(src){
+            Ok(url) => url.host_str().map_or(true, |host| self.allows(host)),
+            Err(_) => true,
+        }
+    }
+}
+
+/// Reloads the policy every time its file changes. A file that can't be
+/// read keeps the last good policy in place.
+pub async fn watch(path: String, policy: SharedPolicy){
+    let path = PathBuf::from(path);
+    let mut modified: Option<SystemTime> = None;
+    let mut interval = tokio::time::interval(Duration::from_secs(RELOAD_INTERVAL));
+    loop{
+        interval.tick().await;
+        let current = match tokio::fs::metadata(&path).await.and_then(|metadata| metadata.modified()){
+            Ok(current) => current,
+            Err(e) => {
+                error!("Can't read domain policy {}. {}", path.display(), e);
+                continue;
+            }
+        };
+        if modified == Some(current){
+            continue;
+        }
+        match DomainPolicy::read(&path.to_string_lossy()).await{
+            Ok(new_policy) => {
+                info!("Domain policy loaded from {}", path.display());
+                *policy.write().unwrap() = new_policy;
+                modified = Some(current);
+            },
+            Err(e) => error!("Can't load domain policy {}. {}", path.display(), e),
+        }
+    }
+}
+
+#[test]
+fn match_domains(){
+    let policy = DomainPolicy::new("blocklist:\n  - spam.com\n  - '*.Bad.net'\n").unwrap();
+    assert!(!policy.allows("spam.com"));
+    assert!(!policy.allows("SPAM.com."));
+    assert!(policy.allows("www.spam.com"));
+    assert!(policy.allows("bad.net"));
+    assert!(!policy.allows("a.b.bad.net"));
+    assert!(policy.allows("notbad.net"));
+    assert!(!policy.allows_url("https://x.bad.net/path"));
+    assert!(policy.allows_url("https://atareao.es"));
+}
+
+#[test]
+fn allow_only_listed(){
+    let policy = DomainPolicy::new("blocklist:\n  - evil.atareao.es\nallowlist:\n  - atareao.es\n  - '*.atareao.es'\n").unwrap();
+    assert!(policy.allows("atareao.es"));
+    assert!(policy.allows("blog.atareao.es"));
+    assert!(!policy.allows("evil.atareao.es"));
+    assert!(!policy.allows("google.es"));
+}